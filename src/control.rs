@@ -0,0 +1,322 @@
+//! Control the systemd service manager via systemctl
+
+use std::{
+    ffi::OsStr,
+    io,
+    process::{Command, ExitStatus, Output},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::properties::{self, PropertyParseError, SystemDProperties};
+
+/// Errors that can occur when running a systemctl control operation
+#[derive(Debug, Error)]
+pub enum ControlError {
+    /// The systemctl command is not installed
+    #[error("systemctl was not found")]
+    SystemctlNotFound,
+
+    /// Systemctl ran, but reported a failure
+    #[error("systemctl failed ({status}): {stderr}")]
+    Failed {
+        /// The exit status of systemctl
+        status: ExitStatus,
+
+        /// What systemctl wrote to stderr
+        stderr: String,
+    },
+
+    /// A command error occured running systemctl
+    #[error("Running systemctl: {0}")]
+    CommandError(#[source] io::Error),
+
+    /// Systemctl did not finish before the timeout, and was killed
+    #[error("systemctl timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+impl From<io::Error> for ControlError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => ControlError::SystemctlNotFound,
+            _ => ControlError::CommandError(err),
+        }
+    }
+}
+
+/// Which service manager to talk to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// The system service manager
+    #[default]
+    System,
+
+    /// The service manager of the calling user (`--user`)
+    User,
+}
+
+/// Options for a control operation
+///
+/// A [Scope] converts into the default options for that scope.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlOptions {
+    /// Which service manager to talk to
+    pub scope: Scope,
+
+    /// Operate on a remote machine, as `[user@]host`, with `systemctl -H`
+    ///
+    /// This connects over SSH, so the host must accept key-based logins
+    /// non-interactively. Connection failures are reported as [ControlError::Failed],
+    /// with SSH's message.
+    pub host: Option<String>,
+
+    /// Log the `systemctl` command line instead of running it
+    pub dry_run: bool,
+
+    /// Kill `systemctl` if it runs for longer than this, e.g. because D-Bus is hung
+    pub timeout: Option<Duration>,
+}
+
+impl ControlOptions {
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("systemctl");
+        if self.scope == Scope::User {
+            cmd.arg("--user");
+        }
+        if let Some(host) = &self.host {
+            cmd.args(["-H", host]);
+        }
+        cmd
+    }
+
+    /// Run a control command, turning a non-zero exit into [ControlError::Failed].
+    fn run(&self, cmd: &mut Command) -> Result<(), ControlError> {
+        if self.dry_run {
+            tracing::info!("Dry run, not running {cmd:?}");
+            return Ok(());
+        }
+        match self.timeout {
+            Some(timeout) => check(
+                properties::output_timeout(cmd, timeout)?.ok_or(ControlError::Timeout(timeout))?,
+            ),
+            None => run(cmd),
+        }
+    }
+}
+
+impl From<Scope> for ControlOptions {
+    fn from(scope: Scope) -> Self {
+        Self {
+            scope,
+            ..Default::default()
+        }
+    }
+}
+
+/// A `systemctl` invocation, configured with builder methods
+///
+/// This collects the options shared by every operation, e.g.
+/// `Systemctl::new().user().host("admin@web1").show("myapp.service")`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Systemctl {
+    options: ControlOptions,
+}
+
+impl Systemctl {
+    /// Talk to the system service manager on this machine
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Talk to the service manager of the calling user (`--user`)
+    pub fn user(mut self) -> Self {
+        self.options.scope = Scope::User;
+        self
+    }
+
+    /// Operate on a remote machine, as `[user@]host`, see [ControlOptions::host]
+    pub fn host<H: Into<String>>(mut self, host: H) -> Self {
+        self.options.host = Some(host.into());
+        self
+    }
+
+    /// Kill `systemctl` if it runs for longer than `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Log control operations instead of running them
+    ///
+    /// Read-only operations, such as [Systemctl::show], still run.
+    pub fn dry_run(mut self) -> Self {
+        self.options.dry_run = true;
+        self
+    }
+
+    /// The options this invocation runs with
+    pub fn options(&self) -> &ControlOptions {
+        &self.options
+    }
+
+    /// Use `systemctl show` to get properties of a systemd unit
+    pub fn show<U: AsRef<OsStr>>(&self, unit: U) -> Result<SystemDProperties, PropertyParseError> {
+        let mut cmd = self.options.command();
+        cmd.arg("show");
+        cmd.arg(unit.as_ref());
+
+        properties::show(&mut cmd, self.options.timeout)
+    }
+
+    /// Use `systemctl start` to start a unit
+    pub fn start<U: AsRef<OsStr>>(&self, unit: U) -> Result<(), ControlError> {
+        self.unit_command("start", unit.as_ref())
+    }
+
+    /// Use `systemctl stop` to stop a unit
+    pub fn stop<U: AsRef<OsStr>>(&self, unit: U) -> Result<(), ControlError> {
+        self.unit_command("stop", unit.as_ref())
+    }
+
+    /// Use `systemctl restart` to restart a unit, starting it if it isn't running
+    pub fn restart<U: AsRef<OsStr>>(&self, unit: U) -> Result<(), ControlError> {
+        self.unit_command("restart", unit.as_ref())
+    }
+
+    /// Use `systemctl reload` to ask a unit to reload its configuration
+    pub fn reload<U: AsRef<OsStr>>(&self, unit: U) -> Result<(), ControlError> {
+        self.unit_command("reload", unit.as_ref())
+    }
+
+    /// Use `systemctl daemon-reload`, see [daemon_reload]
+    pub fn daemon_reload(&self) -> Result<(), ControlError> {
+        daemon_reload(self.options.clone())
+    }
+
+    fn unit_command(&self, verb: &str, unit: &OsStr) -> Result<(), ControlError> {
+        let mut cmd = self.options.command();
+        cmd.arg(verb);
+        cmd.arg(unit);
+
+        self.options.run(&mut cmd)
+    }
+}
+
+impl From<ControlOptions> for Systemctl {
+    fn from(options: ControlOptions) -> Self {
+        Self { options }
+    }
+}
+
+/// Use `systemctl daemon-reload` to reload unit files and rebuild the dependency tree.
+///
+/// This is needed after writing a unit file, before its new contents are reflected
+/// in e.g. [properties](crate::properties::properties).
+pub fn daemon_reload<O: Into<ControlOptions>>(options: O) -> Result<(), ControlError> {
+    let options = options.into();
+    let mut cmd = options.command();
+    cmd.arg("daemon-reload");
+
+    options.run(&mut cmd)
+}
+
+/// Run a control command, turning a non-zero exit into [ControlError::Failed].
+fn run(cmd: &mut Command) -> Result<(), ControlError> {
+    check(cmd.output()?)
+}
+
+/// Turn a non-zero exit into [ControlError::Failed].
+fn check(output: Output) -> Result<(), ControlError> {
+    if !output.status.success() {
+        return Err(ControlError::Failed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_command() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo 'Access denied' >&2; exit 1"]);
+
+        match run(&mut cmd) {
+            Err(ControlError::Failed { status, stderr }) => {
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "Access denied");
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+
+        assert!(run(&mut Command::new("true")).is_ok());
+        assert!(matches!(
+            run(&mut Command::new("systemctl-does-not-exist")),
+            Err(ControlError::SystemctlNotFound)
+        ));
+    }
+
+    #[test]
+    fn remote_host() {
+        let options = ControlOptions {
+            host: Some("admin@web1".into()),
+            ..Scope::User.into()
+        };
+        let cmd = options.command();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["--user", "-H", "admin@web1"]);
+    }
+
+    #[test]
+    fn systemctl_builder() {
+        let systemctl = Systemctl::new()
+            .user()
+            .host("admin@web1")
+            .timeout(Duration::from_secs(5));
+        let cmd = systemctl.options().command();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["--user", "-H", "admin@web1"]);
+        assert_eq!(systemctl.options().timeout, Some(Duration::from_secs(5)));
+
+        let systemctl = Systemctl::new().dry_run();
+        assert!(systemctl.start("does-not-exist.service").is_ok());
+    }
+
+    #[test]
+    fn command_timeout() {
+        let options = ControlOptions {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sleep");
+        cmd.arg("5");
+        assert!(matches!(
+            options.run(&mut cmd),
+            Err(ControlError::Timeout(_))
+        ));
+        assert!(options.run(&mut Command::new("true")).is_ok());
+    }
+
+    #[test]
+    fn dry_run() {
+        let options = ControlOptions {
+            dry_run: true,
+            ..Scope::User.into()
+        };
+        let mut cmd = Command::new("systemctl-does-not-exist");
+        assert!(options.run(&mut cmd).is_ok());
+
+        let options = ControlOptions::from(Scope::User);
+        assert!(matches!(
+            options.run(&mut cmd),
+            Err(ControlError::SystemctlNotFound)
+        ));
+    }
+}
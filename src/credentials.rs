@@ -0,0 +1,119 @@
+//! Read credentials passed to the service by systemd
+//!
+//! Systemd places credentials configured with `LoadCredential=` or `SetCredential=`
+//! in a private directory, named by `$CREDENTIALS_DIRECTORY`. See `systemd.exec(5)`.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// Get the directory holding this service's credentials, from `$CREDENTIALS_DIRECTORY`
+pub fn credentials_directory() -> Option<PathBuf> {
+    std::env::var_os(CREDENTIALS_DIRECTORY)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Read the credential with the given name
+///
+/// Returns `None` if systemd has not passed any credentials, or none with this name.
+pub fn credential(name: &str) -> io::Result<Option<Credential>> {
+    match credentials_directory() {
+        Some(dir) => read_credential(&dir, name),
+        None => Ok(None),
+    }
+}
+
+fn read_credential(dir: &Path, name: &str) -> io::Result<Option<Credential>> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid credential name {name:?}"),
+        ));
+    }
+
+    match std::fs::read(dir.join(name)) {
+        Ok(secret) => Ok(Some(Credential::new(secret))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// A secret value, such as a credential
+///
+/// The value is never shown by [fmt::Debug] or [fmt::Display], which print
+/// `<redacted>`, so that it can't leak into logs when a struct holding it is
+/// printed. It is only available through [Credential::expose_secret], and is
+/// overwritten with zeroes when dropped.
+pub struct Credential {
+    secret: Vec<u8>,
+}
+
+impl Credential {
+    /// Wrap a secret value
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self { secret }
+    }
+
+    /// Access the secret value
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.secret
+    }
+}
+
+impl From<Vec<u8>> for Credential {
+    fn from(secret: Vec<u8>) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Credential(<redacted>)")
+    }
+}
+
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Drop for Credential {
+    fn drop(&mut self) {
+        for byte in self.secret.iter_mut() {
+            // Safety: byte is a valid, aligned reference. A volatile write isn't
+            // optimized away, even though the buffer is about to be freed.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted() {
+        let credential = Credential::new(b"hunter2".to_vec());
+        assert_eq!(format!("{credential}"), "<redacted>");
+        assert_eq!(format!("{credential:?}"), "Credential(<redacted>)");
+        assert_eq!(credential.expose_secret(), b"hunter2");
+    }
+
+    #[test]
+    fn read_credentials() {
+        let dir = std::env::temp_dir().join(format!("systemd-credentials-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("db-password"), "hunter2").unwrap();
+
+        let credential = read_credential(&dir, "db-password").unwrap().unwrap();
+        assert_eq!(credential.expose_secret(), b"hunter2");
+        assert!(read_credential(&dir, "missing").unwrap().is_none());
+        assert!(read_credential(&dir, "../db-password").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,547 @@
+//! Read entries from the systemd journal via journalctl, and send messages to it
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt, io,
+    os::unix::net::UnixDatagram,
+    path::Path,
+    process::{Command, ExitStatus},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use thiserror::Error;
+
+/// Errors that can occur when reading journal entries
+#[derive(Debug, Error)]
+pub enum JournalError {
+    /// A line of journalctl output was not a valid entry
+    #[error("Invalid journal entry: {0}")]
+    InvalidEntry(String),
+
+    /// An expected field is missing from an entry
+    #[error("Missing field {0}")]
+    MissingField(&'static str),
+
+    /// A command error occured running journalctl
+    #[error("Running journalctl: {0}")]
+    CommandError(#[from] io::Error),
+
+    /// Journalctl ran, but reported a failure, e.g. for lack of permission or a bad cursor
+    #[error("journalctl failed ({status}): {stderr}")]
+    Failed {
+        /// The exit status of journalctl
+        status: ExitStatus,
+
+        /// What journalctl wrote to stderr
+        stderr: String,
+    },
+}
+
+/// Use `journalctl -o json` to read the journal entries of a systemd unit.
+///
+/// When `since` is a cursor (from [JournalEntry::cursor]), only entries after it
+/// are returned, which allows paging through the journal.
+pub fn entries<U: AsRef<OsStr>>(
+    unit: U,
+    since: Option<&str>,
+) -> Result<Vec<JournalEntry>, JournalError> {
+    let mut cmd = Command::new("journalctl");
+    cmd.arg("-u");
+    cmd.arg(unit.as_ref());
+    cmd.args(["-o", "json", "--no-pager"]);
+    if let Some(cursor) = since {
+        cmd.arg(format!("--after-cursor={cursor}"));
+    }
+
+    read_entries(&mut cmd)
+}
+
+/// Run a journalctl command, and parse each line of its output as an entry.
+fn read_entries(cmd: &mut Command) -> Result<Vec<JournalEntry>, JournalError> {
+    let output = cmd.output()?;
+
+    if !output.status.success() {
+        return Err(JournalError::Failed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// A single entry from the systemd journal
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    fields: HashMap<String, String>,
+    cursor: String,
+    timestamp: SystemTime,
+}
+
+impl JournalEntry {
+    /// The human readable message of this entry, if any
+    pub fn message(&self) -> Option<&str> {
+        self.field("MESSAGE")
+    }
+
+    /// The syslog priority of this entry
+    pub fn priority(&self) -> Option<Priority> {
+        self.field("PRIORITY")?.parse().ok()
+    }
+
+    /// The wallclock time at which this entry was recorded
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// The cursor of this entry, which identifies its position in the journal
+    pub fn cursor(&self) -> &str {
+        &self.cursor
+    }
+
+    /// Get any field of this entry
+    ///
+    /// Binary fields are decoded as (lossy) UTF-8.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(|s| s.as_str())
+    }
+}
+
+impl FromStr for JournalEntry {
+    type Err = JournalError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Parser::new(s).object()?;
+
+        let cursor = fields
+            .remove("__CURSOR")
+            .ok_or(JournalError::MissingField("__CURSOR"))?;
+
+        let micros = fields
+            .get("__REALTIME_TIMESTAMP")
+            .ok_or(JournalError::MissingField("__REALTIME_TIMESTAMP"))?;
+        let micros: u64 = micros
+            .parse()
+            .map_err(|_| JournalError::InvalidEntry(s.into()))?;
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_micros(micros);
+
+        Ok(JournalEntry {
+            fields,
+            cursor,
+            timestamp,
+        })
+    }
+}
+
+/// The socket on which journald accepts messages in its native protocol
+pub(crate) const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Send a message directly to the journal, at the given priority
+///
+/// This uses journald's native protocol, so the message keeps its priority,
+/// and may span several lines. It does not wait if journald is not keeping up,
+/// and instead fails with [io::ErrorKind::WouldBlock].
+pub fn send(priority: Priority, message: &str) -> io::Result<()> {
+    send_to(Path::new(JOURNAL_SOCKET), priority, message)
+}
+
+pub(crate) fn send_to(socket: &Path, priority: Priority, message: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    datagram.set_nonblocking(true)?;
+    datagram.send_to(&encode(priority, message), socket)?;
+    Ok(())
+}
+
+/// Encode the fields of a journal message in the native protocol.
+fn encode(priority: Priority, message: &str) -> Vec<u8> {
+    let mut payload = format!("PRIORITY={}\n", priority.as_u8()).into_bytes();
+    if message.contains('\n') {
+        // Multi-line values are sent as the name, then the value's length and bytes.
+        payload.extend_from_slice(b"MESSAGE\n");
+        payload.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        payload.extend_from_slice(message.as_bytes());
+        payload.push(b'\n');
+    } else {
+        payload.extend_from_slice(format!("MESSAGE={message}\n").as_bytes());
+    }
+    payload
+}
+
+/// A syslog priority, as used by the journal, from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// The system is unusable
+    Emerg = 0,
+
+    /// Action must be taken immediately
+    Alert = 1,
+
+    /// Critical conditions
+    Crit = 2,
+
+    /// Error conditions
+    Err = 3,
+
+    /// Warning conditions
+    Warning = 4,
+
+    /// Normal, but significant, conditions
+    Notice = 5,
+
+    /// Informational messages
+    Info = 6,
+
+    /// Debug-level messages
+    Debug = 7,
+}
+
+impl Priority {
+    /// The numeric syslog priority, from 0 ([Priority::Emerg]) to 7 ([Priority::Debug])
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+impl From<tracing::Level> for Priority {
+    fn from(level: tracing::Level) -> Self {
+        match level {
+            tracing::Level::ERROR => Priority::Err,
+            tracing::Level::WARN => Priority::Warning,
+            tracing::Level::INFO => Priority::Info,
+            _ => Priority::Debug,
+        }
+    }
+}
+
+/// Error returned when parsing an unknown [Priority]
+#[derive(Debug, Error)]
+#[error("{0} is not a valid priority")]
+pub struct PriorityParseError(String);
+
+impl FromStr for Priority {
+    type Err = PriorityParseError;
+
+    /// Parse a priority from its name, e.g. `warning`, or its number, as `journalctl -p` does
+    fn from_str(s: &str) -> Result<Self, PriorityParseError> {
+        use Priority::*;
+        match s {
+            "emerg" | "0" => Ok(Emerg),
+            "alert" | "1" => Ok(Alert),
+            "crit" | "2" => Ok(Crit),
+            "err" | "3" => Ok(Err),
+            "warning" | "4" => Ok(Warning),
+            "notice" | "5" => Ok(Notice),
+            "info" | "6" => Ok(Info),
+            "debug" | "7" => Ok(Debug),
+            _ => Result::Err(PriorityParseError(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Priority::*;
+        f.write_str(match self {
+            Emerg => "emerg",
+            Alert => "alert",
+            Crit => "crit",
+            Err => "err",
+            Warning => "warning",
+            Notice => "notice",
+            Info => "info",
+            Debug => "debug",
+        })
+    }
+}
+
+/// A minimal parser for the flat JSON objects written by `journalctl -o json`.
+///
+/// Field values are strings, `null`, or arrays of bytes for binary data.
+#[derive(Debug)]
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn invalid(&self) -> JournalError {
+        JournalError::InvalidEntry(self.text.into())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), JournalError> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.invalid());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Consume `c` if it is the next non-whitespace character
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn object(&mut self) -> Result<HashMap<String, String>, JournalError> {
+        let mut fields = HashMap::new();
+
+        self.expect(b'{')?;
+        if !self.eat(b'}') {
+            loop {
+                let key = self.string()?;
+                self.expect(b':')?;
+                if let Some(value) = self.value()? {
+                    fields.insert(key, value);
+                }
+
+                if self.eat(b'}') {
+                    break;
+                }
+                self.expect(b',')?;
+            }
+        }
+
+        self.skip_whitespace();
+        if self.pos != self.text.len() {
+            return Err(self.invalid());
+        }
+
+        Ok(fields)
+    }
+
+    fn value(&mut self) -> Result<Option<String>, JournalError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'"') => self.string().map(Some),
+            Some(b'[') => self.bytes().map(Some),
+            _ => {
+                let token = self.token();
+                match token {
+                    "null" => Ok(None),
+                    "" => Err(self.invalid()),
+                    _ => Ok(Some(token.into())),
+                }
+            }
+        }
+    }
+
+    /// A bare token, such as a number or `null`
+    fn token(&mut self) -> &'a str {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'+' | b'.'))
+        {
+            self.pos += 1;
+        }
+        &self.text[start..self.pos]
+    }
+
+    /// An array of byte values, used by the journal for binary fields
+    fn bytes(&mut self) -> Result<String, JournalError> {
+        let mut bytes = Vec::new();
+
+        self.expect(b'[')?;
+        if !self.eat(b']') {
+            loop {
+                self.skip_whitespace();
+                let byte = self.token().parse().map_err(|_| self.invalid())?;
+                bytes.push(byte);
+
+                if self.eat(b']') {
+                    break;
+                }
+                self.expect(b',')?;
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn string(&mut self) -> Result<String, JournalError> {
+        let mut value = String::new();
+
+        self.expect(b'"')?;
+        loop {
+            let rest = &self.text[self.pos..];
+            let end = rest.find(['"', '\\']).ok_or_else(|| self.invalid())?;
+            value.push_str(&rest[..end]);
+            self.pos += end + 1;
+
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(value);
+            }
+
+            let escape = self.peek().ok_or_else(|| self.invalid())?;
+            self.pos += 1;
+            match escape {
+                b'"' => value.push('"'),
+                b'\\' => value.push('\\'),
+                b'/' => value.push('/'),
+                b'b' => value.push('\u{8}'),
+                b'f' => value.push('\u{c}'),
+                b'n' => value.push('\n'),
+                b'r' => value.push('\r'),
+                b't' => value.push('\t'),
+                b'u' => {
+                    let high = self.hex()?;
+                    let c = if (0xD800..0xDC00).contains(&high) {
+                        // A surrogate pair encodes characters outside the BMP.
+                        if !self.text[self.pos..].starts_with("\\u") {
+                            return Err(self.invalid());
+                        }
+                        self.pos += 2;
+                        let low = self.hex()?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return Err(self.invalid());
+                        }
+                        let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                        char::from_u32(code)
+                    } else {
+                        char::from_u32(high)
+                    };
+                    value.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                _ => return Err(self.invalid()),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, JournalError> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.invalid())?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| self.invalid())?;
+        self.pos += 4;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_messages() {
+        assert_eq!(
+            encode(Priority::Info, "Serving"),
+            b"PRIORITY=6\nMESSAGE=Serving\n"
+        );
+
+        let mut expected = b"PRIORITY=4\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(encode(Priority::Warning, "a\nb"), expected);
+    }
+
+    #[test]
+    fn send_message() {
+        let path = std::env::temp_dir().join(format!("systemd-journal-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let journal = UnixDatagram::bind(&path).unwrap();
+
+        send_to(&path, Priority::Info, "Serving").unwrap();
+        let mut buf = [0; 64];
+        let n = journal.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"PRIORITY=6\nMESSAGE=Serving\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parse_entry() {
+        let entry: JournalEntry = r#"{"__CURSOR":"s=abc;i=1","__REALTIME_TIMESTAMP":"1700000000123456","PRIORITY":"6","MESSAGE":"Started \"app\" é 😀","_PID":null}"#
+            .parse()
+            .unwrap();
+
+        assert_eq!(entry.cursor(), "s=abc;i=1");
+        assert_eq!(entry.priority(), Some(Priority::Info));
+        assert_eq!(entry.message(), Some("Started \"app\" é 😀"));
+        assert_eq!(entry.field("_PID"), None);
+        assert_eq!(
+            entry.timestamp(),
+            SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)
+        );
+    }
+
+    #[test]
+    fn parse_priority() {
+        assert_eq!("warning".parse::<Priority>().unwrap(), Priority::Warning);
+        assert_eq!("0".parse::<Priority>().unwrap(), Priority::Emerg);
+        assert!("loud".parse::<Priority>().is_err());
+
+        assert_eq!(Priority::Notice.as_u8(), 5);
+        assert_eq!(Priority::Crit.to_string(), "crit");
+        assert!(Priority::Err < Priority::Debug);
+
+        assert_eq!(Priority::from(tracing::Level::WARN), Priority::Warning);
+        assert_eq!(Priority::from(tracing::Level::TRACE), Priority::Debug);
+    }
+
+    #[test]
+    fn parse_binary_message() {
+        let entry: JournalEntry =
+            r#"{ "__CURSOR" : "c", "__REALTIME_TIMESTAMP" : "0", "MESSAGE" : [104, 105] }"#
+                .parse()
+                .unwrap();
+
+        assert_eq!(entry.message(), Some("hi"));
+    }
+
+    #[test]
+    fn parse_invalid_entry() {
+        assert!(matches!(
+            r#"{"__REALTIME_TIMESTAMP":"0"}"#.parse::<JournalEntry>(),
+            Err(JournalError::MissingField("__CURSOR"))
+        ));
+        assert!(matches!(
+            r#"{"__CURSOR":"c","#.parse::<JournalEntry>(),
+            Err(JournalError::InvalidEntry(_))
+        ));
+        assert!(matches!(
+            r#"{"__CURSOR":"c","__REALTIME_TIMESTAMP":"0","MESSAGE":"\ud83d\u0041"}"#
+                .parse::<JournalEntry>(),
+            Err(JournalError::InvalidEntry(_))
+        ));
+    }
+
+    #[test]
+    fn failed_journalctl() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo 'No journal files were opened' >&2; exit 1"]);
+
+        match read_entries(&mut cmd) {
+            Err(JournalError::Failed { status, stderr }) => {
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "No journal files were opened");
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+        assert!(read_entries(&mut Command::new("true")).unwrap().is_empty());
+    }
+}
@@ -5,31 +5,117 @@
 //! It eschews the use of libsystemd bindings in favor of using the `systemctl` command line utility
 //! and environment variables to interact with systemd.
 
-#[cfg(feature = "notify")]
+pub mod control;
+pub mod credentials;
+pub mod journal;
+#[cfg(any(feature = "notify", feature = "async-io", feature = "blocking"))]
 pub mod notify;
+#[cfg(feature = "pressure")]
+pub mod pressure;
 pub mod properties;
+#[cfg(feature = "notify")]
+pub mod service;
 pub mod socket;
 
+pub use self::socket::listen_owned_fds;
 pub use self::socket::sockets;
+pub use self::socket::sockets_map;
+pub use self::socket::sockets_tcp;
+pub use self::socket::sockets_tcp_filtered;
+pub use self::socket::SystemDCommandExt;
 pub use self::socket::SystemDSocket;
+pub use self::socket::SystemDSockets;
 
 /// Check if the current process is running under systemd as a service with the given unit name
-pub fn is_systemd(unit: &str) -> bool {
-    if let Ok(properties) = self::properties::properties(unit) {
-        let systemd_pid = properties.property("MainPID");
-        let process_pid = std::process::id().to_string();
-
-        tracing::trace!(
-            MainPID = ?systemd_pid,
-            SelfPID = ?process_pid,
-            "Systemd detected, checking for PID match"
-        );
+///
+/// Any error reading the unit's properties is treated as not running under systemd.
+/// Use [is_systemd_checked] to tell those errors apart.
+pub fn is_systemd<U: AsRef<std::ffi::OsStr>>(unit: U) -> bool {
+    // If we can't read the properties, we're not running under systemd
+    is_systemd_checked(unit).unwrap_or(false)
+}
 
-        return systemd_pid == Some(&process_pid);
+/// Check if the current process is running under systemd as a service with the given unit name
+///
+/// Unlike [is_systemd], this reports why the unit's properties could not be read,
+/// including [PropertyParseError::UnitNotFound] when systemd has no such unit,
+/// which usually means the unit name is wrong.
+///
+/// [PropertyParseError::UnitNotFound]: self::properties::PropertyParseError::UnitNotFound
+pub fn is_systemd_checked<U: AsRef<std::ffi::OsStr>>(
+    unit: U,
+) -> Result<bool, self::properties::PropertyParseError> {
+    let unit = unit.as_ref();
+    let properties = self::properties::properties(unit)?;
+    check_loaded(unit, &properties)?;
+
+    Ok(is_systemd_from(&properties))
+}
+
+/// Systemctl reports a unit it doesn't know of with `LoadState=not-found`, rather than failing.
+fn check_loaded(
+    unit: &std::ffi::OsStr,
+    properties: &self::properties::SystemDProperties,
+) -> Result<(), self::properties::PropertyParseError> {
+    if properties.property("LoadState") == Some("not-found") {
+        return Err(self::properties::PropertyParseError::UnitNotFound(
+            unit.to_string_lossy().into_owned(),
+        ));
     }
+    Ok(())
+}
 
-    // If we can't read the properties, we're not running under systemd
-    false
+/// Check if the current process is the main process of the unit with these properties
+///
+/// This avoids running `systemctl` again when the properties are already at hand.
+pub fn is_systemd_from(properties: &self::properties::SystemDProperties) -> bool {
+    let systemd_pid = properties.main_pid();
+    let process_pid = std::process::id();
+
+    tracing::trace!(
+        MainPID = ?systemd_pid,
+        SelfPID = ?process_pid,
+        "Systemd detected, checking for PID match"
+    );
+
+    systemd_pid == Some(process_pid)
+}
+
+/// The type of container the process is running in, e.g. `docker` or `systemd-nspawn`
+///
+/// This follows systemd's convention: container managers set `$container`, and
+/// systemd copies it to `/run/systemd/container` for processes which don't inherit
+/// its environment. Returns `None` outside of a container.
+pub fn in_container() -> Option<String> {
+    container_from(
+        std::env::var("container").ok(),
+        std::path::Path::new(CONTAINER_FILE),
+    )
+}
+
+const CONTAINER_FILE: &str = "/run/systemd/container";
+
+fn container_from(var: Option<String>, file: &std::path::Path) -> Option<String> {
+    var.or_else(|| std::fs::read_to_string(file).ok())
+        .map(|container| container.trim().to_owned())
+        .filter(|container| !container.is_empty())
+}
+
+/// The current time on `CLOCK_MONOTONIC`, which systemd uses for `MONOTONIC_USEC=`
+/// and `*TimestampMonotonic` properties.
+pub(crate) fn monotonic_now() -> std::io::Result<std::time::Duration> {
+    // Safety: timespec is plain-old-data, all zeroes is a valid value.
+    let mut now: libc::timespec = unsafe { std::mem::zeroed() };
+
+    // Safety: clock_gettime only writes into the provided timespec.
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(std::time::Duration::new(
+        now.tv_sec as u64,
+        now.tv_nsec as u32,
+    ))
 }
 
 #[cfg(test)]
@@ -43,4 +129,56 @@ mod tests {
             "Tests should not be running under the automoton.service systemd unit"
         );
     }
+
+    #[test]
+    fn test_is_systemd_from() {
+        let properties: properties::SystemDProperties =
+            format!("ActiveState=active\nMainPID={}", std::process::id())
+                .parse()
+                .unwrap();
+        assert!(is_systemd_from(&properties));
+
+        let properties: properties::SystemDProperties =
+            "ActiveState=inactive\nMainPID=0".parse().unwrap();
+        assert!(!is_systemd_from(&properties));
+    }
+
+    #[test]
+    fn container_type() {
+        let file = std::env::temp_dir().join(format!("systemd-container-{}", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(container_from(None, &file), None);
+        assert_eq!(
+            container_from(Some("docker".into()), &file).as_deref(),
+            Some("docker")
+        );
+
+        std::fs::write(&file, "systemd-nspawn\n").unwrap();
+        assert_eq!(
+            container_from(None, &file).as_deref(),
+            Some("systemd-nspawn")
+        );
+        assert_eq!(container_from(Some(String::new()), &file), None);
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn unit_not_found() {
+        let unit = std::ffi::OsStr::new("typo.service");
+        let properties: properties::SystemDProperties =
+            "ActiveState=inactive\nLoadState=not-found\nMainPID=0"
+                .parse()
+                .unwrap();
+        assert!(matches!(
+            check_loaded(unit, &properties),
+            Err(properties::PropertyParseError::UnitNotFound(name)) if name == "typo.service"
+        ));
+
+        let properties: properties::SystemDProperties =
+            "ActiveState=inactive\nLoadState=loaded\nMainPID=0"
+                .parse()
+                .unwrap();
+        assert!(check_loaded(unit, &properties).is_ok());
+    }
 }
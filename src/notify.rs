@@ -1,16 +1,59 @@
 //! Notify systemd of service status changes
+//!
+//! The `notify` feature uses tokio for async IO. With only the `async-io`
+//! feature enabled, the async-io reactor (behind async-std and smol) is used
+//! instead, with the same API. If both features are enabled, e.g. by different
+//! crates in a dependency graph, tokio takes precedence, so the socket must then
+//! be used from within a tokio runtime.
 
-use std::{fmt, io, sync::Arc};
+use std::ffi::OsString;
+#[cfg(any(feature = "notify", feature = "async-io"))]
+use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+#[cfg(any(feature = "notify", feature = "async-io"))]
+use std::path::PathBuf;
+use std::str::FromStr;
+#[cfg(any(feature = "notify", feature = "async-io"))]
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io, mem, ptr};
 
-use camino::Utf8PathBuf;
 use thiserror::Error;
-use tokio::net::UnixDatagram;
 
+#[cfg(all(feature = "async-io", not(feature = "notify")))]
+mod async_io_backend;
+pub mod blocking;
+#[cfg(feature = "notify")]
+mod tokio_backend;
+
+#[cfg(all(feature = "async-io", not(feature = "notify")))]
+use self::async_io_backend as backend;
+#[cfg(feature = "notify")]
+use self::tokio_backend as backend;
+
+#[cfg(any(feature = "notify", feature = "async-io"))]
+use self::backend::Datagram;
+
+use crate::monotonic_now;
 use crate::socket::SocketError;
 
 /// The environment variable that systemd uses to set the unix socket path
 /// for notifications.
-const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+pub(crate) const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// The largest message systemd accepts on the notification socket (`PIPE_BUF`)
+///
+/// Systemd ignores longer datagrams, so they are rejected with
+/// [NotifyError::MessageTooLarge] before sending.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// The environment variable that systemd uses to set the watchdog interval, in microseconds.
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+
+/// The environment variable that systemd uses to set which process should ping the watchdog.
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
 
 /// Error returned when sending a notification didn't work
 #[derive(Debug, Error)]
@@ -26,6 +69,49 @@ pub enum NotifyError {
     /// An environment variable had an invalid value
     #[error("Invalid ${0}={1}")]
     InvalidVar(&'static str, String),
+
+    /// The message contained no notifications
+    #[error("Message is empty")]
+    EmptyMessage,
+
+    /// Systemd did not respond before the timeout elapsed
+    #[error("Timed out waiting for systemd")]
+    Timeout,
+
+    /// A raw assignment was not a valid `KEY=value` line
+    #[error("Invalid notify assignment {0:?}")]
+    InvalidAssignment(String),
+
+    /// Nothing is listening on the notification socket any more
+    #[error("Notification socket is gone: {0}")]
+    SocketGone(#[source] io::Error),
+
+    /// The serialized message, of this many bytes, is too large to send
+    #[error("Message of {0} bytes is too large to send")]
+    MessageTooLarge(usize),
+
+    /// No file descriptors were given to store, so `FDSTORE=1` would do nothing
+    #[error("No file descriptors to store")]
+    MissingFds,
+}
+
+impl NotifyError {
+    /// Classify an error from sending a datagram of `len` bytes to the notification socket.
+    fn from_send(err: io::Error, len: usize) -> Self {
+        match err.raw_os_error() {
+            Some(libc::ECONNREFUSED | libc::ENOENT) => NotifyError::SocketGone(err),
+            Some(libc::EMSGSIZE) => NotifyError::MessageTooLarge(len),
+            _ => NotifyError::IO(err),
+        }
+    }
+}
+
+/// Reject payloads which systemd would ignore, see [MAX_MESSAGE_SIZE].
+fn check_size(payload: &[u8]) -> Result<(), NotifyError> {
+    if payload.len() > MAX_MESSAGE_SIZE {
+        return Err(NotifyError::MessageTooLarge(payload.len()));
+    }
+    Ok(())
 }
 
 impl From<SocketError> for NotifyError {
@@ -48,10 +134,53 @@ pub struct CustomVariable {
 
 impl fmt::Display for CustomVariable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "X-{}={}", self.key, self.value)
+        write!(f, "X-{}=", self.key)?;
+        write_single_line(f, &self.value)
+    }
+}
+
+/// A single `KEY=value` assignment, for [Notification::Raw]
+///
+/// This can only be built from a valid assignment, so that it can't inject
+/// further assignments into a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawAssignment(String);
+
+impl RawAssignment {
+    /// Check that `line` is a single assignment, with an uppercase key
+    ///
+    /// Invalid lines are rejected with [NotifyError::InvalidAssignment].
+    pub fn new(line: &str) -> Result<Self, NotifyError> {
+        validate_assignment(line)?;
+        Ok(Self(line.into()))
+    }
+
+    /// The assignment, as it is sent
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RawAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
+/// Write a free-text value, replacing newlines with spaces.
+///
+/// Each line of a notification datagram is a separate assignment, so an
+/// embedded newline would otherwise inject extra fields into the message.
+fn write_single_line(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    for (i, line) in value.lines().enumerate() {
+        if i > 0 {
+            f.write_str(" ")?;
+        }
+        f.write_str(line)?;
+    }
+    Ok(())
+}
+
 /// Notification kinds to send to systemd
 #[derive(Debug, Clone)]
 pub enum Notification {
@@ -76,8 +205,34 @@ pub enum Notification {
     /// Notify systemd to trigger the watchdog
     WatchdogTrigger,
 
+    /// Notify systemd of a D-Bus error name describing a failure
+    BusError(String),
+
+    /// Ask systemd to extend the current start, stop or reload timeout
+    ///
+    /// The timeout is extended to the given duration from now.
+    ExtendTimeout(Duration),
+
+    /// The `CLOCK_MONOTONIC` time at which a reload started, sent with [Notification::Reloading]
+    Monotonic(Duration),
+
+    /// Tell systemd the PID of the service's main process, e.g. after forking a new one
+    MainPid(u32),
+
     /// Send a custom notification
     Custom(CustomVariable),
+
+    /// Any other `KEY=value` assignment, sent as-is
+    Raw(RawAssignment),
+}
+
+impl Notification {
+    /// Report the OS error behind `err` to systemd, as `ERRNO=`
+    ///
+    /// Returns `None` for errors which did not come from the OS, and so have no errno.
+    pub fn from_io_error(err: &io::Error) -> Option<Notification> {
+        err.raw_os_error().map(Notification::Errno)
+    }
 }
 
 impl fmt::Display for Notification {
@@ -86,17 +241,89 @@ impl fmt::Display for Notification {
             Notification::Ready => f.write_str("READY=1"),
             Notification::Reloading => f.write_str("RELOADING=1"),
             Notification::Stopping => f.write_str("STOPPING=1"),
-            Notification::Status(status) => write!(f, "STATUS={status}"),
+            Notification::Status(status) => {
+                f.write_str("STATUS=")?;
+                write_single_line(f, status)
+            }
             Notification::Errno(errno) => write!(f, "ERRNO={errno}"),
             Notification::WatchdogOk => f.write_str("WATCHDOG=1"),
             Notification::WatchdogTrigger => f.write_str("WATCHDOG=trigger"),
+            Notification::BusError(error) => {
+                f.write_str("BUSERROR=")?;
+                write_single_line(f, error)
+            }
+            Notification::ExtendTimeout(timeout) => {
+                write!(f, "EXTEND_TIMEOUT_USEC={}", timeout.as_micros())
+            }
+            Notification::Monotonic(time) => write!(f, "MONOTONIC_USEC={}", time.as_micros()),
+            Notification::MainPid(pid) => write!(f, "MAINPID={pid}"),
             Notification::Custom(variable) => write!(f, "{variable}"),
+            Notification::Raw(assignment) => write!(f, "{assignment}"),
+        }
+    }
+}
+
+impl FromStr for Notification {
+    type Err = NotifyError;
+
+    /// Parse a single `KEY=value` line, as sent to the notification socket
+    ///
+    /// Known assignments are parsed into their variants, `X-` prefixed keys into
+    /// [Notification::Custom], and anything else into [Notification::Raw], so that
+    /// the parsed notification displays as the original line.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || NotifyError::InvalidAssignment(line.into());
+
+        if let Some(custom) = line.strip_prefix("X-") {
+            let (key, value) = custom.split_once('=').ok_or_else(invalid)?;
+            if key.is_empty() || value.chars().any(char::is_control) {
+                return Err(invalid());
+            }
+            return Ok(Notification::Custom(CustomVariable {
+                key: key.into(),
+                value: value.into(),
+            }));
         }
+
+        validate_assignment(line)?;
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        let usec = || {
+            value
+                .parse()
+                .map(Duration::from_micros)
+                .map_err(|_| invalid())
+        };
+
+        Ok(match (key, value) {
+            ("READY", "1") => Notification::Ready,
+            ("RELOADING", "1") => Notification::Reloading,
+            ("STOPPING", "1") => Notification::Stopping,
+            ("STATUS", status) => Notification::Status(status.into()),
+            ("ERRNO", errno) => Notification::Errno(errno.parse().map_err(|_| invalid())?),
+            ("WATCHDOG", "1") => Notification::WatchdogOk,
+            ("WATCHDOG", "trigger") => Notification::WatchdogTrigger,
+            ("BUSERROR", error) => Notification::BusError(error.into()),
+            ("EXTEND_TIMEOUT_USEC", _) => Notification::ExtendTimeout(usec()?),
+            ("MONOTONIC_USEC", _) => Notification::Monotonic(usec()?),
+            ("MAINPID", pid) => Notification::MainPid(
+                pid.parse()
+                    .ok()
+                    .filter(|pid| *pid != 0)
+                    .ok_or_else(invalid)?,
+            ),
+            _ => Notification::Raw(RawAssignment(line.into())),
+        })
     }
 }
 
 /// A systemd notification message, which
 /// can consist of a series of known or custom systemd variables.
+///
+/// Systemd treats each datagram as a single, atomic state update, so related
+/// notifications (e.g. `READY=1` and `STATUS=...`) should be batched into one
+/// `Message` rather than sent separately.
+///
+/// When displayed, each notification is written as one line, terminated by a newline.
 #[derive(Debug, Clone, Default)]
 pub struct Message {
     variables: Vec<Notification>,
@@ -110,10 +337,91 @@ impl Message {
         }
     }
 
+    /// Parse a received notification datagram, e.g. to relay or inspect what a service sent
+    ///
+    /// Each line is parsed as a [Notification], and empty lines, including a
+    /// trailing newline, are skipped.
+    pub fn from_datagram(bytes: &[u8]) -> Result<Message, NotifyError> {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| NotifyError::InvalidAssignment(String::from_utf8_lossy(bytes).into()))?;
+        text.split('\n')
+            .filter(|line| !line.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
     /// Add a notification to the message
     pub fn push(&mut self, notification: Notification) {
         self.variables.push(notification)
     }
+
+    /// Append another message's notifications to this one
+    ///
+    /// This lets independent components contribute to a single datagram, which
+    /// systemd applies as one update. Where both set the same variable, e.g.
+    /// `STATUS=`, the value from `other` is sent last, and so wins.
+    pub fn merge(&mut self, other: Message) {
+        self.variables.extend(other.variables)
+    }
+
+    /// Iterate over the notifications in the message, in the order they are sent
+    pub fn iter(&self) -> std::slice::Iter<'_, Notification> {
+        self.variables.iter()
+    }
+
+    /// Check if the message contains no notifications
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    /// Render the message as a datagram, without a trailing newline
+    ///
+    /// The [fmt::Display] implementation ends every assignment with a newline,
+    /// which systemd accepts. This is for stricter consumers which expect
+    /// assignments to be separated, rather than terminated, by newlines.
+    pub fn to_datagram_bytes(&self) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        for (i, variable) in self.variables.iter().enumerate() {
+            if i > 0 {
+                datagram.push(b'\n');
+            }
+            datagram.extend_from_slice(variable.to_string().as_bytes());
+        }
+        datagram
+    }
+
+    /// Shorten the last `STATUS=` so that the message fits in [MAX_MESSAGE_SIZE]
+    ///
+    /// This is for services which put long diagnostics into their status, and
+    /// would rather send part of it than fail with [NotifyError::MessageTooLarge].
+    /// Messages which are too large for other reasons are left as they are.
+    pub fn truncate_status(&mut self) {
+        let Some(index) = self
+            .variables
+            .iter()
+            .rposition(|variable| matches!(variable, Notification::Status(_)))
+        else {
+            return;
+        };
+
+        // Line breaks are collapsed when serialized, so this may take more than one pass.
+        let mut len = self.to_string().len();
+        while len > MAX_MESSAGE_SIZE {
+            let Notification::Status(status) = &mut self.variables[index] else {
+                return;
+            };
+            if status.is_empty() {
+                return;
+            }
+
+            let mut end = status.len().saturating_sub(len - MAX_MESSAGE_SIZE);
+            while !status.is_char_boundary(end) {
+                end -= 1;
+            }
+            status.truncate(end);
+            len = self.to_string().len();
+        }
+    }
 }
 
 impl From<Notification> for Message {
@@ -131,6 +439,12 @@ impl FromIterator<Notification> for Message {
     }
 }
 
+impl Extend<Notification> for Message {
+    fn extend<I: IntoIterator<Item = Notification>>(&mut self, iter: I) {
+        self.variables.extend(iter)
+    }
+}
+
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for variable in &self.variables {
@@ -143,42 +457,975 @@ impl fmt::Display for Message {
 /// Notification socket for sending messages to Systemd
 ///
 /// The default construction is to build this from the environment via [SystemDNotify::from_environment].
+#[cfg(any(feature = "notify", feature = "async-io"))]
 #[derive(Debug, Clone)]
 pub struct SystemDNotify {
-    socket: Arc<UnixDatagram>,
-    address: Utf8PathBuf,
+    socket: Arc<Datagram>,
+    address: PathBuf,
+    connected: bool,
+    credentials: bool,
 }
 
+#[cfg(any(feature = "notify", feature = "async-io"))]
 impl SystemDNotify {
     /// Create a new SystemDNotify client from the environment
     pub fn from_environment() -> Result<Self, NotifyError> {
-        let address = crate::socket::var(NOTIFY_SOCKET)?.into();
-        let socket = UnixDatagram::unbound()?;
+        Self::connect(notify_socket()?)
+    }
+
+    /// Create a new SystemDNotify client which sends to the given socket path
+    ///
+    /// This bypasses `$NOTIFY_SOCKET`, which is useful for testing, or for
+    /// proxying notifications to another socket.
+    ///
+    /// The socket is connected to the path once here, so that each send doesn't
+    /// need to resolve it. If that fails, e.g. because nothing is listening yet,
+    /// every send is addressed to the path instead.
+    pub fn connect<P: Into<PathBuf>>(path: P) -> Result<Self, NotifyError> {
+        let socket = Datagram::unbound()?;
+        let address = path.into();
+        let connected = connect_socket(socket.as_fd(), &address);
 
         Ok(SystemDNotify {
             socket: Arc::new(socket),
             address,
+            connected,
+            credentials: false,
         })
     }
 
+    /// Attach our credentials (`SCM_CREDENTIALS`) to every message sent
+    ///
+    /// This lets systemd attribute notifications to this process, even when they
+    /// arrive after it has forked or from a helper process, so that they are not
+    /// ignored under a stricter `NotifyAccess=`.
+    pub fn with_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
     /// Send a message to systemd
+    ///
+    /// The message is sent as a single datagram. Empty messages are rejected
+    /// with [NotifyError::EmptyMessage], since systemd ignores them.
     pub async fn send<M: Into<Message>>(&self, message: M) -> Result<(), NotifyError> {
-        let message = message.into().to_string();
+        let message = message.into();
+        if message.is_empty() {
+            return Err(NotifyError::EmptyMessage);
+        }
+
+        self.send_datagram(message.to_string().as_bytes(), &[])
+            .await
+    }
+
+    /// Send a message to systemd, giving up after `timeout`
+    ///
+    /// A send only waits if the socket's buffer is full, e.g. because systemd is
+    /// stuck, but then this returns [NotifyError::Timeout] rather than hanging,
+    /// which matters for watchdog pings in particular. The message is not sent
+    /// if the timeout elapses.
+    pub async fn send_timeout<M: Into<Message>>(
+        &self,
+        message: M,
+        timeout: Duration,
+    ) -> Result<(), NotifyError> {
+        backend::timeout(timeout, self.send(message))
+            .await
+            .unwrap_or(Err(NotifyError::Timeout))
+    }
+
+    /// Send a message to systemd without waiting
+    ///
+    /// This is for contexts which can't await, such as `Drop`. The message
+    /// is dropped if the socket is not immediately writable.
+    #[cfg(feature = "notify")]
+    pub(crate) fn try_send<M: Into<Message>>(&self, message: M) -> Result<(), NotifyError> {
+        let message = message.into();
+        if message.is_empty() {
+            return Err(NotifyError::EmptyMessage);
+        }
+
+        let message = message.to_string();
+        check_size(message.as_bytes())?;
+        send_message(
+            self.socket.as_fd(),
+            (!self.connected).then_some(self.address.as_path()),
+            message.as_bytes(),
+            &[],
+            self.credentials,
+        )
+        .map_err(|err| NotifyError::from_send(err, message.len()))?;
+        Ok(())
+    }
+
+    /// Send a datagram to systemd, passing along `fds`
+    async fn send_datagram(&self, payload: &[u8], fds: &[RawFd]) -> Result<(), NotifyError> {
+        check_size(payload)?;
         self.socket
-            .send_to(message.as_bytes(), &self.address)
+            .send_with(|| {
+                send_message(
+                    self.socket.as_fd(),
+                    (!self.connected).then_some(self.address.as_path()),
+                    payload,
+                    fds,
+                    self.credentials,
+                )
+            })
+            .await
+            .map_err(|err| NotifyError::from_send(err, payload.len()))?;
+        Ok(())
+    }
+
+    /// Send an arbitrary `KEY=value` assignment to systemd
+    ///
+    /// This is an escape hatch for variables which [Notification] doesn't cover.
+    /// The line is checked to be a single assignment, but the caller is responsible
+    /// for the key and value meaning what they intend to systemd.
+    pub async fn send_raw(&self, line: &str) -> Result<(), NotifyError> {
+        validate_assignment(line)?;
+        self.send_datagram(format!("{line}\n").as_bytes(), &[])
+            .await
+    }
+
+    /// Send a series of notifications to systemd as a single message
+    pub async fn send_all<I>(&self, notifications: I) -> Result<(), NotifyError>
+    where
+        I: IntoIterator<Item = Notification>,
+    {
+        self.send(notifications.into_iter().collect::<Message>())
+            .await
+    }
+
+    /// Send a watchdog keep-alive (`WATCHDOG=1`) to systemd
+    ///
+    /// This should be called at least once every `WatchdogSec=` interval.
+    pub async fn ping_watchdog(&self) -> Result<(), NotifyError> {
+        self.send(Notification::WatchdogOk).await
+    }
+
+    /// Ping the watchdog in the background, but only while `check` reports the service healthy
+    ///
+    /// The watchdog interval is read from the environment, see [watchdog_interval],
+    /// and `None` is returned if the watchdog is disabled. At each ping, `check` is
+    /// called, and `WATCHDOG=1` is only sent if it returns `true`. A failing check
+    /// intentionally sends nothing, so that systemd's watchdog fires and, with
+    /// `Restart=on-watchdog` or similar, restarts the service.
+    ///
+    /// The pings run on a spawned tokio task, so this is only available with the
+    /// `notify` feature, and must be called from within a tokio runtime. Abort the
+    /// task to stop pinging.
+    #[cfg(feature = "notify")]
+    pub fn spawn_watchdog_with<F>(&self, check: F) -> Option<tokio::task::JoinHandle<()>>
+    where
+        F: Fn() -> bool + Send + 'static,
+    {
+        let interval = watchdog_interval()?;
+        Some(crate::service::spawn_watchdog(
+            self.clone(),
+            interval,
+            crate::service::DEFAULT_WATCHDOG_JITTER,
+            check,
+        ))
+    }
+
+    /// Tell systemd the service is unhealthy (`WATCHDOG=trigger`)
+    ///
+    /// Systemd handles this as if the watchdog interval had elapsed.
+    pub async fn trigger_watchdog(&self) -> Result<(), NotifyError> {
+        self.send(Notification::WatchdogTrigger).await
+    }
+
+    /// Tell systemd that the service has started reloading
+    ///
+    /// This sends `RELOADING=1` with the current `MONOTONIC_USEC=`, as required for
+    /// `Type=notify-reload` services. Follow it with [SystemDNotify::reload_done]
+    /// once the reload has finished, or systemd considers the unit stuck reloading.
+    pub async fn reload_begin(&self) -> Result<(), NotifyError> {
+        self.send_all([
+            Notification::Reloading,
+            Notification::Monotonic(monotonic_now()?),
+        ])
+        .await
+    }
+
+    /// Tell systemd that the service has finished reloading, and is ready again
+    pub async fn reload_done(&self) -> Result<(), NotifyError> {
+        self.send(Notification::Ready).await
+    }
+
+    /// Store file descriptors in systemd's fd store (`FDSTORE=1`)
+    ///
+    /// Stored descriptors are passed back to the service when it is restarted,
+    /// with `name` as their `$LISTEN_FDNAMES` entry. Systemd normally polls stored
+    /// descriptors and removes them on `POLLHUP` or `POLLERR`; pass `poll: false`
+    /// to send `FDPOLL=0` for descriptors whose lifecycle the service manages itself.
+    ///
+    /// Systemd only accepts names of at most 255 printable ASCII characters, other
+    /// than `:`, so any other name is rejected with [NotifyError::InvalidAssignment].
+    pub async fn store_fds(
+        &self,
+        fds: &[BorrowedFd<'_>],
+        name: Option<&str>,
+        poll: bool,
+    ) -> Result<(), NotifyError> {
+        if fds.is_empty() {
+            return Err(NotifyError::MissingFds);
+        }
+
+        let mut message = String::from("FDSTORE=1\n");
+        if let Some(name) = name {
+            let assignment = format!("FDNAME={name}");
+            if !fdname_is_valid(name) {
+                return Err(NotifyError::InvalidAssignment(assignment));
+            }
+            message.push_str(&assignment);
+            message.push('\n');
+        }
+        if !poll {
+            message.push_str("FDPOLL=0\n");
+        }
+
+        let fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+        self.send_datagram(message.as_bytes(), &fds).await
+    }
+
+    /// Wait for systemd to process all previously sent notifications
+    ///
+    /// This sends `BARRIER=1` along with the write end of a pipe, then waits for
+    /// systemd to close its copy, which it does only after handling every earlier
+    /// message. See `sd_notify_barrier(3)`.
+    pub async fn barrier(&self, timeout: Duration) -> Result<(), NotifyError> {
+        let (read, write) = pipe()?;
+
+        self.send_datagram(b"BARRIER=1\n", &[write.as_raw_fd()])
             .await?;
+        drop(write);
+
+        // Systemd never writes to the pipe, so the read end only becomes
+        // readable once every write end has been closed.
+        if !backend::readable(read, timeout).await? {
+            return Err(NotifyError::Timeout);
+        }
+
         Ok(())
     }
 }
 
+/// Read the notification socket path from `$NOTIFY_SOCKET`.
+///
+/// The path is used as raw bytes, so it need not be UTF-8. An empty value is
+/// reported as invalid rather than missing, since it points at a misconfigured
+/// unit rather than a process which isn't under systemd.
+fn notify_socket() -> Result<OsString, NotifyError> {
+    notify_socket_from(std::env::var_os(NOTIFY_SOCKET))
+}
+
+fn notify_socket_from(value: Option<OsString>) -> Result<OsString, NotifyError> {
+    let value = value.ok_or(NotifyError::MissingVar(NOTIFY_SOCKET))?;
+    non_empty(NOTIFY_SOCKET, value)
+}
+
+fn non_empty(name: &'static str, value: OsString) -> Result<OsString, NotifyError> {
+    if value.is_empty() {
+        return Err(NotifyError::InvalidVar(name, String::new()));
+    }
+    Ok(value)
+}
+
+/// Check that `line` is a single `KEY=value` assignment.
+///
+/// Keys must be non-empty and made of uppercase letters, digits and `_`, as systemd's
+/// own are. Values may contain `=`, but no control characters such as newlines.
+fn validate_assignment(line: &str) -> Result<(), NotifyError> {
+    let invalid = || NotifyError::InvalidAssignment(line.into());
+
+    let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+    let key_valid = !key.is_empty()
+        && key
+            .bytes()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == b'_');
+
+    if !key_valid || value.chars().any(char::is_control) {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Check a name for stored descriptors, following systemd's `fdname_is_valid`.
+#[cfg(any(feature = "notify", feature = "async-io"))]
+fn fdname_is_valid(name: &str) -> bool {
+    name.len() <= 255 && name.bytes().all(|c| (b' '..0x7f).contains(&c) && c != b':')
+}
+
+/// Create a non-blocking, close-on-exec pipe, returning the (read, write) ends.
+#[cfg(any(feature = "notify", feature = "async-io"))]
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+
+    // Safety: `fds` has room for the two descriptors pipe2 writes.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Safety: pipe2 succeeded, so both descriptors are open and owned by us.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Build a `sockaddr_un` for the given notification socket path.
+///
+/// Paths starting with `@` refer to the abstract socket namespace.
+fn socket_address(path: &Path) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    // Safety: sockaddr_un is plain-old-data, all zeroes is a valid value.
+    let mut address: libc::sockaddr_un = unsafe { mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    // Abstract socket names start with a NUL byte and are not NUL-terminated.
+    let path = path.as_os_str().as_bytes();
+    let (offset, name, terminator) = match path.strip_prefix(b"@") {
+        Some(name) => (1, name, 0),
+        None => (0, path, 1),
+    };
+
+    if offset + name.len() + terminator > address.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "notify socket path is too long",
+        ));
+    }
+
+    for (dst, src) in address.sun_path[offset..].iter_mut().zip(name) {
+        *dst = *src as libc::c_char;
+    }
+
+    let length = mem::size_of::<libc::sa_family_t>() + offset + name.len() + terminator;
+    Ok((address, length as libc::socklen_t))
+}
+
+/// Connect `socket` to the notification socket at `path`, returning whether it succeeded.
+fn connect_socket(socket: BorrowedFd<'_>, path: &Path) -> bool {
+    let Ok((address, address_len)) = socket_address(path) else {
+        return false;
+    };
+
+    // Safety: address is a valid sockaddr_un of address_len bytes.
+    let rc = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            ptr::addr_of!(address).cast(),
+            address_len,
+        )
+    };
+    if rc < 0 {
+        tracing::trace!(
+            "Sending notifications unconnected: {}",
+            io::Error::last_os_error()
+        );
+    }
+    rc == 0
+}
+
+/// Send a datagram to `address`, or to the connected peer if `None`, passing `fds`
+/// along as `SCM_RIGHTS` ancillary data, and optionally our `SCM_CREDENTIALS`.
+fn send_message(
+    socket: BorrowedFd<'_>,
+    address: Option<&Path>,
+    payload: &[u8],
+    fds: &[RawFd],
+    credentials: bool,
+) -> io::Result<usize> {
+    let mut address = address.map(socket_address).transpose()?;
+
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+
+    let fds_len = mem::size_of_val(fds) as libc::c_uint;
+    let cred_len = mem::size_of::<libc::ucred>() as libc::c_uint;
+
+    // Safety: CMSG_SPACE is a pure computation on the payload length.
+    let mut control_len = 0;
+    if !fds.is_empty() {
+        control_len += unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+    }
+    if credentials {
+        control_len += unsafe { libc::CMSG_SPACE(cred_len) } as usize;
+    }
+    // Use u64 storage so the buffer is suitably aligned for cmsghdr.
+    let mut control = vec![0u64; control_len.div_ceil(mem::size_of::<u64>())];
+
+    // Safety: msghdr is plain-old-data, all zeroes is a valid value.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    if let Some((address, address_len)) = &mut address {
+        msg.msg_name = ptr::addr_of_mut!(*address).cast();
+        msg.msg_namelen = *address_len;
+    }
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if control_len > 0 {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control_len as _;
+
+        // Safety: the control buffer is large enough and aligned for a cmsghdr
+        // carrying `fds.len()` descriptors, followed by one carrying a ucred.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+            if !fds.is_empty() {
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+                ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+
+            if credentials {
+                let cred = libc::ucred {
+                    pid: libc::getpid(),
+                    uid: libc::getuid(),
+                    gid: libc::getgid(),
+                };
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_CREDENTIALS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(cred_len) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), cred);
+            }
+        }
+    }
+
+    // Safety: msg and everything it points to outlives the call.
+    let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(sent as usize)
+}
+
+/// Check whether systemd has provided a socket for notifications.
+///
+/// This only inspects `$NOTIFY_SOCKET`, and does not try to open a socket.
+pub fn available() -> bool {
+    std::env::var_os(NOTIFY_SOCKET).is_some_and(|socket| !socket.is_empty())
+}
+
+/// Get the interval within which systemd expects watchdog pings, from `$WATCHDOG_USEC`
+///
+/// Returns `None` if the watchdog is disabled, or if `$WATCHDOG_PID` names another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var(WATCHDOG_PID) {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec = std::env::var(WATCHDOG_USEC).ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
+
 /// Notify systemd that this service is ready.
 ///
 /// This is implemented as sending a single message to systemd with the appropriate
 /// ready command.
+#[cfg(any(feature = "notify", feature = "async-io"))]
 pub async fn ready() {
     if let Ok(notify) = SystemDNotify::from_environment() {
-        if let Err(err) = notify.send(Notification::Ready).await {
-            tracing::warn!("Failed to notify systemd: {err}");
+        match notify.send(Notification::Ready).await {
+            Ok(()) => {}
+            Err(err @ NotifyError::SocketGone(_)) => {
+                tracing::trace!("Not notifying systemd: {err}");
+            }
+            Err(err) => tracing::warn!("Failed to notify systemd: {err}"),
         }
     }
 }
+
+/// Notify systemd that this service is ready, and wait until it has processed that.
+///
+/// This avoids the race where a short-lived `Type=notify` process exits before
+/// systemd has handled its readiness notification.
+#[cfg(any(feature = "notify", feature = "async-io"))]
+pub async fn ready_synced(timeout: Duration) -> Result<(), NotifyError> {
+    let notify = SystemDNotify::from_environment()?;
+    notify.send(Notification::Ready).await?;
+    notify.barrier(timeout).await
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "notify", feature = "async-io"))]
+mod tests {
+    use super::*;
+
+    use std::future::Future;
+    use tokio::net::UnixDatagram;
+
+    /// Run a future to completion on a single-threaded runtime
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn errno_from_io_error() {
+        let err = io::Error::from_raw_os_error(libc::EACCES);
+        assert_eq!(
+            Notification::from_io_error(&err).unwrap().to_string(),
+            format!("ERRNO={}", libc::EACCES)
+        );
+        assert!(Notification::from_io_error(&io::Error::other("custom")).is_none());
+    }
+
+    #[test]
+    fn parse_notification() {
+        let round_trip = |line: &str| {
+            let notification: Notification = line.parse().unwrap();
+            assert_eq!(notification.to_string(), line);
+            notification
+        };
+
+        assert!(matches!(round_trip("READY=1"), Notification::Ready));
+        assert!(matches!(round_trip("RELOADING=1"), Notification::Reloading));
+        assert!(matches!(round_trip("STOPPING=1"), Notification::Stopping));
+        assert!(matches!(round_trip("STATUS=a=b"), Notification::Status(s) if s == "a=b"));
+        assert!(matches!(round_trip("ERRNO=13"), Notification::Errno(13)));
+        assert!(matches!(round_trip("WATCHDOG=1"), Notification::WatchdogOk));
+        assert!(matches!(
+            round_trip("WATCHDOG=trigger"),
+            Notification::WatchdogTrigger
+        ));
+        assert!(matches!(
+            round_trip("BUSERROR=org.freedesktop.DBus.Error.Failed"),
+            Notification::BusError(_)
+        ));
+        assert!(matches!(
+            round_trip("EXTEND_TIMEOUT_USEC=5000000"),
+            Notification::ExtendTimeout(t) if t == Duration::from_secs(5)
+        ));
+        assert!(matches!(
+            round_trip("MONOTONIC_USEC=1500"),
+            Notification::Monotonic(t) if t == Duration::from_micros(1500)
+        ));
+        assert!(matches!(
+            round_trip("X-PLUGIN=loaded"),
+            Notification::Custom(_)
+        ));
+        assert!(matches!(
+            round_trip("MAINPID=4242"),
+            Notification::MainPid(4242)
+        ));
+        assert!(matches!(round_trip("READY=0"), Notification::Raw(_)));
+        assert_eq!(
+            RawAssignment::new("FDSTORE=1").unwrap().as_str(),
+            "FDSTORE=1"
+        );
+        assert!(RawAssignment::new("A=1\nREADY=1").is_err());
+
+        assert!("ERRNO=abc".parse::<Notification>().is_err());
+        assert!("MAINPID=0".parse::<Notification>().is_err());
+        assert!("MAINPID=-1".parse::<Notification>().is_err());
+        assert!("MAINPID=abc".parse::<Notification>().is_err());
+        assert!("EXTEND_TIMEOUT_USEC=-1".parse::<Notification>().is_err());
+        assert!("X-=1".parse::<Notification>().is_err());
+        assert!("READY".parse::<Notification>().is_err());
+        assert!("READY=1\nSTATUS=x".parse::<Notification>().is_err());
+    }
+
+    #[test]
+    fn parse_datagram() {
+        let message = Message::from_datagram(b"READY=1\n\nSTATUS=up\nX-PLUGIN=a\n").unwrap();
+        assert_eq!(message.to_string(), "READY=1\nSTATUS=up\nX-PLUGIN=a\n");
+
+        let message = Message::from_datagram(b"WATCHDOG=1").unwrap();
+        assert_eq!(message.to_string(), "WATCHDOG=1\n");
+
+        assert!(Message::from_datagram(b"\n").unwrap().is_empty());
+        assert!(matches!(
+            Message::from_datagram(b"READY=1\nnot an assignment\n"),
+            Err(NotifyError::InvalidAssignment(line)) if line == "not an assignment"
+        ));
+        assert!(Message::from_datagram(b"STATUS=\xff").is_err());
+    }
+
+    #[test]
+    fn multiline_status() {
+        let message = Message::from(Notification::Status("one\ntwo\r\nthree".into()));
+        assert_eq!(message.to_string(), "STATUS=one two three\n");
+    }
+
+    #[test]
+    fn truncate_status() {
+        let mut message: Message = [
+            Notification::Ready,
+            Notification::Status("é".repeat(MAX_MESSAGE_SIZE)),
+        ]
+        .into_iter()
+        .collect();
+        message.truncate_status();
+        let len = message.to_string().len();
+        assert!((MAX_MESSAGE_SIZE - 1..=MAX_MESSAGE_SIZE).contains(&len));
+        assert!(message.to_string().starts_with("READY=1\nSTATUS=éé"));
+
+        let mut message = Message::from(Notification::Status("short".into()));
+        message.truncate_status();
+        assert_eq!(message.to_string(), "STATUS=short\n");
+    }
+
+    #[test]
+    fn merge_messages() {
+        let mut message = Message::from(Notification::Ready);
+        message.merge(Message::from(Notification::Status("plugin up".into())));
+        message.extend([Notification::WatchdogOk]);
+        assert_eq!(
+            message.to_string(),
+            "READY=1\nSTATUS=plugin up\nWATCHDOG=1\n"
+        );
+    }
+
+    #[test]
+    fn datagram_bytes() {
+        let message: Message = [Notification::Ready, Notification::Status("up".into())]
+            .into_iter()
+            .collect();
+        assert_eq!(message.to_string(), "READY=1\nSTATUS=up\n");
+        assert_eq!(message.to_datagram_bytes(), b"READY=1\nSTATUS=up");
+        assert!(Message::new().to_datagram_bytes().is_empty());
+    }
+
+    #[test]
+    fn raw_assignments() {
+        assert!(validate_assignment("NEWTHING=1").is_ok());
+        assert!(validate_assignment("STATUS=a=b").is_ok());
+        assert!(validate_assignment("X_CUSTOM=").is_ok());
+        assert!(validate_assignment("NOEQUALS").is_err());
+        assert!(validate_assignment("=value").is_err());
+        assert!(validate_assignment("lower=1").is_err());
+        assert!(validate_assignment("READY=1\nSTATUS=x").is_err());
+    }
+
+    #[test]
+    fn empty_notify_socket() {
+        assert!(matches!(
+            non_empty(NOTIFY_SOCKET, OsString::new()),
+            Err(NotifyError::InvalidVar(NOTIFY_SOCKET, value)) if value.is_empty()
+        ));
+        assert_eq!(
+            non_empty(NOTIFY_SOCKET, "/run/systemd/notify".into()).unwrap(),
+            "/run/systemd/notify"
+        );
+
+        // Socket paths are bytes, and need not be valid UTF-8.
+        let path = std::ffi::OsStr::from_bytes(b"@notify-\xff");
+        let (address, len) = socket_address(Path::new(path)).unwrap();
+        assert_eq!(address.sun_path[0], 0);
+        assert_eq!(address.sun_path[8] as u8, 0xff);
+        assert_eq!(len as usize, mem::size_of::<libc::sa_family_t>() + 9);
+    }
+
+    #[test]
+    fn extend_timeout() {
+        let message = Message::from(Notification::ExtendTimeout(Duration::from_secs(90)));
+        assert_eq!(message.to_string(), "EXTEND_TIMEOUT_USEC=90000000\n");
+    }
+
+    #[test]
+    fn bus_error() {
+        let message = Message::from(Notification::BusError(
+            "org.freedesktop.DBus.Error.TimedOut".into(),
+        ));
+        assert_eq!(
+            message.to_string(),
+            "BUSERROR=org.freedesktop.DBus.Error.TimedOut\n"
+        );
+    }
+
+    /// A stand-in for systemd's notification socket
+    #[derive(Debug)]
+    struct FakeSystemD {
+        socket: UnixDatagram,
+        path: PathBuf,
+    }
+
+    impl FakeSystemD {
+        fn bind(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("systemd-notify-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let socket = UnixDatagram::bind(&path).unwrap();
+            Self { socket, path }
+        }
+
+        fn client(&self) -> SystemDNotify {
+            SystemDNotify::connect(self.path.clone()).unwrap()
+        }
+
+        async fn recv(&self) -> String {
+            let mut buf = vec![0; 4096];
+            let n = self.socket.recv(&mut buf).await.unwrap();
+            String::from_utf8(buf[..n].to_vec()).unwrap()
+        }
+    }
+
+    impl Drop for FakeSystemD {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    #[test]
+    fn send_ready() {
+        run(async {
+            let systemd = FakeSystemD::bind("ready");
+            systemd.client().send(Notification::Ready).await.unwrap();
+            assert_eq!(systemd.recv().await, "READY=1\n");
+        })
+    }
+
+    #[test]
+    fn send_too_large() {
+        run(async {
+            let systemd = FakeSystemD::bind("too-large");
+            let notify = systemd.client();
+
+            let mut message = Message::from(Notification::Status("x".repeat(MAX_MESSAGE_SIZE)));
+            assert!(matches!(
+                notify.send(message.clone()).await,
+                Err(NotifyError::MessageTooLarge(len)) if len == MAX_MESSAGE_SIZE + 8
+            ));
+
+            message.truncate_status();
+            notify.send(message).await.unwrap();
+            assert_eq!(systemd.recv().await.len(), MAX_MESSAGE_SIZE);
+        })
+    }
+
+    #[test]
+    fn send_with_timeout() {
+        run(async {
+            let systemd = FakeSystemD::bind("send-timeout");
+            let notify = systemd.client();
+            notify
+                .send_timeout(Notification::WatchdogOk, Duration::from_secs(1))
+                .await
+                .unwrap();
+            assert_eq!(systemd.recv().await, "WATCHDOG=1\n");
+
+            // Fill the socket's queue, so that the next send would wait indefinitely.
+            let filler = std::os::unix::net::UnixDatagram::unbound().unwrap();
+            filler.connect(&systemd.path).unwrap();
+            filler.set_nonblocking(true).unwrap();
+            while filler.send(b"WATCHDOG=1\n").is_ok() {}
+            let result = notify
+                .send_timeout(Notification::WatchdogOk, Duration::from_millis(20))
+                .await;
+            assert!(matches!(result, Err(NotifyError::Timeout)), "{result:?}");
+        })
+    }
+
+    #[test]
+    fn send_unconnected() {
+        run(async {
+            let path = std::env::temp_dir()
+                .join(format!("systemd-notify-unconnected-{}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            // Nothing is listening yet, so the client falls back to addressing each send.
+            let notify = SystemDNotify::connect(path.clone()).unwrap();
+            assert!(!notify.connected);
+
+            let systemd = FakeSystemD {
+                socket: UnixDatagram::bind(&path).unwrap(),
+                path,
+            };
+            notify.send(Notification::Ready).await.unwrap();
+            assert_eq!(systemd.recv().await, "READY=1\n");
+            assert!(systemd.client().connected);
+        })
+    }
+
+    #[test]
+    fn send_status() {
+        run(async {
+            let systemd = FakeSystemD::bind("status");
+            systemd
+                .client()
+                .send(Notification::Status("foo".into()))
+                .await
+                .unwrap();
+            assert_eq!(systemd.recv().await, "STATUS=foo\n");
+        })
+    }
+
+    #[test]
+    fn send_multiple() {
+        run(async {
+            let systemd = FakeSystemD::bind("multiple");
+            systemd
+                .client()
+                .send_all([Notification::Ready, Notification::Status("up".into())])
+                .await
+                .unwrap();
+            assert_eq!(systemd.recv().await, "READY=1\nSTATUS=up\n");
+        })
+    }
+
+    #[test]
+    fn watchdog() {
+        run(async {
+            let systemd = FakeSystemD::bind("watchdog");
+            let notify = systemd.client();
+
+            notify.ping_watchdog().await.unwrap();
+            assert_eq!(systemd.recv().await, "WATCHDOG=1\n");
+
+            notify.trigger_watchdog().await.unwrap();
+            assert_eq!(systemd.recv().await, "WATCHDOG=trigger\n");
+        })
+    }
+
+    #[test]
+    fn send_with_credentials() {
+        run(async {
+            let systemd = FakeSystemD::bind("credentials");
+            systemd
+                .client()
+                .with_credentials()
+                .send(Notification::Ready)
+                .await
+                .unwrap();
+            assert_eq!(systemd.recv().await, "READY=1\n");
+        })
+    }
+
+    #[test]
+    fn store_fds() {
+        run(async {
+            let systemd = FakeSystemD::bind("fdstore");
+            let (read, write) = pipe().unwrap();
+            let notify = systemd.client();
+
+            notify
+                .store_fds(&[read.as_fd()], Some("pipe"), true)
+                .await
+                .unwrap();
+            assert_eq!(systemd.recv().await, "FDSTORE=1\nFDNAME=pipe\n");
+
+            notify
+                .store_fds(&[write.as_fd()], None, false)
+                .await
+                .unwrap();
+            assert_eq!(systemd.recv().await, "FDSTORE=1\nFDPOLL=0\n");
+
+            for name in ["x\nREADY=1", "a:b", &"n".repeat(256)] {
+                assert!(matches!(
+                    notify.store_fds(&[read.as_fd()], Some(name), true).await,
+                    Err(NotifyError::InvalidAssignment(_))
+                ));
+            }
+            assert!(matches!(
+                notify.store_fds(&[], Some("pipe"), true).await,
+                Err(NotifyError::MissingFds)
+            ));
+        })
+    }
+
+    #[test]
+    fn reload() {
+        run(async {
+            let systemd = FakeSystemD::bind("reload");
+            let notify = systemd.client();
+
+            notify.reload_begin().await.unwrap();
+            let message = systemd.recv().await;
+            let usec = message
+                .strip_prefix("RELOADING=1\nMONOTONIC_USEC=")
+                .and_then(|rest| rest.strip_suffix('\n'))
+                .unwrap();
+            assert!(usec.parse::<u64>().unwrap() > 0);
+
+            notify.reload_done().await.unwrap();
+            assert_eq!(systemd.recv().await, "READY=1\n");
+        })
+    }
+
+    #[test]
+    fn send_empty() {
+        run(async {
+            let systemd = FakeSystemD::bind("empty");
+            let result = systemd.client().send(Message::new()).await;
+            assert!(matches!(result, Err(NotifyError::EmptyMessage)));
+        })
+    }
+
+    #[test]
+    fn send_socket_gone() {
+        run(async {
+            let path =
+                std::env::temp_dir().join(format!("systemd-notify-gone-{}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let notify = SystemDNotify::connect(path.clone()).unwrap();
+
+            // The socket file remains, but nothing is listening.
+            drop(std::os::unix::net::UnixDatagram::bind(&path).unwrap());
+            let result = notify.send(Notification::Ready).await;
+            assert!(matches!(result, Err(NotifyError::SocketGone(_))));
+
+            std::fs::remove_file(&path).unwrap();
+            let result = notify.send(Notification::Ready).await;
+            assert!(matches!(result, Err(NotifyError::SocketGone(_))));
+        })
+    }
+
+    #[test]
+    fn from_environment() {
+        run(async {
+            let systemd = FakeSystemD::bind("environment");
+
+            // Setting the real variable would leak into tests running in parallel.
+            let path = notify_socket_from(Some(systemd.path.clone().into())).unwrap();
+            let notify = SystemDNotify::connect(path).unwrap();
+            notify.send(Notification::Stopping).await.unwrap();
+            assert_eq!(systemd.recv().await, "STOPPING=1\n");
+
+            assert!(matches!(
+                notify_socket_from(None),
+                Err(NotifyError::MissingVar(NOTIFY_SOCKET))
+            ));
+        })
+    }
+
+    #[test]
+    fn barrier() {
+        run(async {
+            let systemd = FakeSystemD::bind("barrier");
+            let notify = systemd.client();
+
+            // Systemd releases the barrier pipe once it has read the message.
+            let task = tokio::spawn(async move {
+                notify.send(Notification::Ready).await?;
+                notify.barrier(Duration::from_secs(5)).await
+            });
+
+            assert_eq!(systemd.recv().await, "READY=1\n");
+            assert_eq!(systemd.recv().await, "BARRIER=1\n");
+            task.await.unwrap().unwrap();
+        })
+    }
+
+    #[test]
+    fn barrier_timeout() {
+        run(async {
+            let systemd = FakeSystemD::bind("barrier-timeout");
+
+            // Nobody reads the barrier message, so the pipe is never released.
+            let result = systemd.client().barrier(Duration::from_millis(50)).await;
+            assert!(matches!(result, Err(NotifyError::Timeout)));
+        })
+    }
+}
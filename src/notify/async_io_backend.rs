@@ -0,0 +1,53 @@
+//! The async-io implementation of the notification socket, for async-std and smol
+
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use async_io::{Async, Timer};
+use futures_lite::future;
+
+/// A non-blocking, unbound unix datagram socket
+#[derive(Debug)]
+pub(super) struct Datagram(Async<UnixDatagram>);
+
+impl Datagram {
+    pub(super) fn unbound() -> io::Result<Self> {
+        Async::new(UnixDatagram::unbound()?).map(Datagram)
+    }
+
+    /// Run `send` once the socket is writable, retrying if it would block.
+    pub(super) async fn send_with<F>(&self, mut send: F) -> io::Result<usize>
+    where
+        F: FnMut() -> io::Result<usize>,
+    {
+        self.0.write_with(|_| send()).await
+    }
+}
+
+impl AsFd for Datagram {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+/// Wait until `fd` is readable, returning `false` if the timeout elapses first.
+pub(super) async fn readable(fd: OwnedFd, timeout: Duration) -> io::Result<bool> {
+    let fd = Async::new(fd)?;
+    future::or(async { fd.readable().await.map(|_| true) }, async {
+        Timer::after(timeout).await;
+        Ok(false)
+    })
+    .await
+}
+
+/// Run `future`, returning `None` if the timeout elapses first.
+pub(super) async fn timeout<F: Future>(timeout: Duration, future: F) -> Option<F::Output> {
+    future::or(async { Some(future.await) }, async {
+        Timer::after(timeout).await;
+        None
+    })
+    .await
+}
@@ -0,0 +1,164 @@
+//! A blocking notification client, for services without an async runtime
+
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::{
+    check_size, connect_socket, monotonic_now, notify_socket, send_message, validate_assignment,
+    Message, Notification, NotifyError,
+};
+
+/// Blocking notification socket for sending messages to Systemd
+///
+/// This mirrors the async `SystemDNotify`, but sends from the calling thread.
+#[derive(Debug, Clone)]
+pub struct SystemDNotify {
+    socket: Arc<UnixDatagram>,
+    address: PathBuf,
+    connected: bool,
+    credentials: bool,
+}
+
+impl SystemDNotify {
+    /// Create a new SystemDNotify client from the environment
+    pub fn from_environment() -> Result<Self, NotifyError> {
+        Self::connect(notify_socket()?)
+    }
+
+    /// Create a new SystemDNotify client which sends to the given socket path
+    ///
+    /// As with the async client, the socket is connected to the path up front when possible.
+    pub fn connect<P: Into<PathBuf>>(path: P) -> Result<Self, NotifyError> {
+        let socket = UnixDatagram::unbound()?;
+        let address = path.into();
+        let connected = connect_socket(socket.as_fd(), &address);
+
+        Ok(SystemDNotify {
+            socket: Arc::new(socket),
+            address,
+            connected,
+            credentials: false,
+        })
+    }
+
+    /// Attach our credentials (`SCM_CREDENTIALS`) to every message sent
+    pub fn with_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    /// Send a message to systemd
+    ///
+    /// The message is sent as a single datagram. Empty messages are rejected
+    /// with [NotifyError::EmptyMessage], since systemd ignores them.
+    pub fn send<M: Into<Message>>(&self, message: M) -> Result<(), NotifyError> {
+        let message = message.into();
+        if message.is_empty() {
+            return Err(NotifyError::EmptyMessage);
+        }
+
+        self.send_datagram(message.to_string().as_bytes())
+    }
+
+    /// Send an arbitrary `KEY=value` assignment to systemd
+    ///
+    /// The caller is responsible for the key and value meaning what they intend to systemd.
+    pub fn send_raw(&self, line: &str) -> Result<(), NotifyError> {
+        validate_assignment(line)?;
+        self.send_datagram(format!("{line}\n").as_bytes())
+    }
+
+    fn send_datagram(&self, payload: &[u8]) -> Result<(), NotifyError> {
+        check_size(payload)?;
+        send_message(
+            self.socket.as_fd(),
+            (!self.connected).then_some(self.address.as_path()),
+            payload,
+            &[],
+            self.credentials,
+        )
+        .map_err(|err| NotifyError::from_send(err, payload.len()))?;
+        Ok(())
+    }
+
+    /// Send a series of notifications to systemd as a single message
+    pub fn send_all<I>(&self, notifications: I) -> Result<(), NotifyError>
+    where
+        I: IntoIterator<Item = Notification>,
+    {
+        self.send(notifications.into_iter().collect::<Message>())
+    }
+
+    /// Send a watchdog keep-alive (`WATCHDOG=1`) to systemd
+    pub fn ping_watchdog(&self) -> Result<(), NotifyError> {
+        self.send(Notification::WatchdogOk)
+    }
+
+    /// Tell systemd the service is unhealthy (`WATCHDOG=trigger`)
+    pub fn trigger_watchdog(&self) -> Result<(), NotifyError> {
+        self.send(Notification::WatchdogTrigger)
+    }
+
+    /// Tell systemd that the service has started reloading, with `MONOTONIC_USEC=`
+    pub fn reload_begin(&self) -> Result<(), NotifyError> {
+        self.send_all([
+            Notification::Reloading,
+            Notification::Monotonic(monotonic_now()?),
+        ])
+    }
+
+    /// Tell systemd that the service has finished reloading, and is ready again
+    pub fn reload_done(&self) -> Result<(), NotifyError> {
+        self.send(Notification::Ready)
+    }
+}
+
+/// Notify systemd that this service is ready, without an async runtime.
+///
+/// Like the async `ready`, errors are logged rather than returned.
+pub fn ready_blocking() {
+    if let Ok(notify) = SystemDNotify::from_environment() {
+        match notify.send(Notification::Ready) {
+            Ok(()) => {}
+            Err(err @ NotifyError::SocketGone(_)) => {
+                tracing::trace!("Not notifying systemd: {err}");
+            }
+            Err(err) => tracing::warn!("Failed to notify systemd: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_blocking() {
+        let path =
+            std::env::temp_dir().join(format!("systemd-notify-blocking-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notify = SystemDNotify::connect(path.clone()).unwrap();
+        notify
+            .send_all([Notification::Ready, Notification::Status("up".into())])
+            .unwrap();
+
+        let mut buf = [0; 4096];
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=up\n");
+
+        notify.send_raw("NEWTHING=1").unwrap();
+        let n = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"NEWTHING=1\n");
+
+        assert!(matches!(
+            notify.send(Message::new()),
+            Err(NotifyError::EmptyMessage)
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! The tokio implementation of the notification socket
+
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::time::Duration;
+
+use tokio::io::{unix::AsyncFd, Interest};
+use tokio::net::UnixDatagram;
+
+/// A non-blocking, unbound unix datagram socket
+#[derive(Debug)]
+pub(super) struct Datagram(UnixDatagram);
+
+impl Datagram {
+    pub(super) fn unbound() -> io::Result<Self> {
+        UnixDatagram::unbound().map(Datagram)
+    }
+
+    /// Run `send` once the socket is writable, retrying if it would block.
+    pub(super) async fn send_with<F>(&self, send: F) -> io::Result<usize>
+    where
+        F: FnMut() -> io::Result<usize>,
+    {
+        self.0.async_io(Interest::WRITABLE, send).await
+    }
+}
+
+impl AsFd for Datagram {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+/// Wait until `fd` is readable, returning `false` if the timeout elapses first.
+pub(super) async fn readable(fd: OwnedFd, timeout: Duration) -> io::Result<bool> {
+    let fd = AsyncFd::with_interest(fd, Interest::READABLE)?;
+    match tokio::time::timeout(timeout, fd.readable()).await {
+        Ok(guard) => guard.map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Run `future`, returning `None` if the timeout elapses first.
+pub(super) async fn timeout<F: Future>(timeout: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(timeout, future).await.ok()
+}
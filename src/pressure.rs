@@ -0,0 +1,252 @@
+//! Watch for memory pressure notifications configured by systemd
+//!
+//! Systemd passes `$MEMORY_PRESSURE_WATCH`, the path of a pressure stall
+//! information (PSI) file such as the unit's `memory.pressure`, and
+//! `$MEMORY_PRESSURE_WRITE`, a base64 encoded trigger to write to it. A service
+//! can then poll the file and shed load (e.g. drop caches) when it becomes ready.
+//! See `MemoryPressureWatch=` in `systemd.resource-control(5)`.
+
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use thiserror::Error;
+
+const MEMORY_PRESSURE_WATCH: &str = "MEMORY_PRESSURE_WATCH";
+const MEMORY_PRESSURE_WRITE: &str = "MEMORY_PRESSURE_WRITE";
+
+/// The watch path systemd uses to indicate that memory pressure handling is off
+const DISABLED: &str = "/dev/null";
+
+/// Errors that can occur when setting up a memory pressure watch
+#[derive(Debug, Error)]
+pub enum PressureError {
+    /// An IO error occurred opening or configuring the pressure file
+    #[error("{}", .0)]
+    IO(#[from] io::Error),
+
+    /// Invalid value for a systemd variable
+    #[error("Invalid ${0}={1}")]
+    InvalidVar(&'static str, String),
+}
+
+/// Get the path to watch for memory pressure events, from `$MEMORY_PRESSURE_WATCH`
+///
+/// Returns `None` if the variable is unset, or if systemd has disabled
+/// memory pressure handling by pointing it at `/dev/null`.
+pub fn memory_pressure_watch() -> Option<Utf8PathBuf> {
+    let path = std::env::var(MEMORY_PRESSURE_WATCH).ok()?;
+    if path.is_empty() || path == DISABLED {
+        return None;
+    }
+    Some(path.into())
+}
+
+/// Get the trigger to write to the watched path, decoded from `$MEMORY_PRESSURE_WRITE`
+///
+/// For PSI files this is a threshold such as `some 200000 2000000`, i.e. a
+/// stall of 200ms in any 2s window.
+pub fn memory_pressure_write() -> Result<Option<Vec<u8>>, PressureError> {
+    match std::env::var(MEMORY_PRESSURE_WRITE) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => base64_decode(&value)
+            .map(Some)
+            .ok_or(PressureError::InvalidVar(MEMORY_PRESSURE_WRITE, value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(value)) => Err(PressureError::InvalidVar(
+            MEMORY_PRESSURE_WRITE,
+            value.to_string_lossy().into_owned(),
+        )),
+    }
+}
+
+/// An open memory pressure watch
+///
+/// The descriptor can be registered with an event loop, or waited on directly
+/// with [MemoryPressure::wait].
+#[derive(Debug)]
+pub struct MemoryPressure {
+    fd: OwnedFd,
+    events: libc::c_short,
+}
+
+impl MemoryPressure {
+    /// Open the memory pressure watch that systemd configured for this service
+    ///
+    /// Returns `None` when no watch is configured.
+    pub fn from_environment() -> Result<Option<Self>, PressureError> {
+        let Some(path) = memory_pressure_watch() else {
+            return Ok(None);
+        };
+        let trigger = memory_pressure_write()?;
+
+        Self::open(&path, trigger.as_deref()).map(Some)
+    }
+
+    /// Open a memory pressure watch on the given path, writing `trigger` to it
+    ///
+    /// PSI files are watched for `POLLPRI`. Unix sockets are connected to, and
+    /// they and FIFOs are watched for incoming data, as in `sd_event_add_memory_pressure(3)`.
+    pub fn open(path: &Utf8Path, trigger: Option<&[u8]>) -> Result<Self, PressureError> {
+        let kind = std::fs::metadata(path)?.file_type();
+
+        let (fd, events) = if kind.is_socket() {
+            let stream = UnixStream::connect(path)?;
+            stream.set_nonblocking(true)?;
+            (OwnedFd::from(stream), libc::POLLIN)
+        } else {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path)?;
+            let events = if kind.is_fifo() {
+                libc::POLLIN
+            } else {
+                libc::POLLPRI
+            };
+            (OwnedFd::from(file), events)
+        };
+
+        if let Some(trigger) = trigger {
+            std::fs::File::from(fd.try_clone()?).write_all(trigger)?;
+        }
+
+        Ok(Self { fd, events })
+    }
+
+    /// Wait for a memory pressure event
+    ///
+    /// Returns `true` if an event occurred, and `false` if the timeout elapsed first.
+    /// A timeout of `None` waits indefinitely. Data received from a socket or FIFO
+    /// is discarded, so that the next call waits for a new event.
+    pub fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: self.events,
+            revents: 0,
+        };
+        let timeout = timeout
+            .map(|t| t.as_millis().min(libc::c_int::MAX as u128) as libc::c_int)
+            .unwrap_or(-1);
+
+        // Safety: pollfd is valid for the duration of the call.
+        let rc = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if rc == 0 {
+            return Ok(false);
+        }
+
+        if self.events == libc::POLLIN {
+            self.drain()?;
+        }
+
+        Ok(true)
+    }
+
+    fn drain(&self) -> io::Result<()> {
+        let mut file = std::fs::File::from(self.fd.try_clone()?);
+        let mut buf = [0; 256];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl AsRawFd for MemoryPressure {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for MemoryPressure {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Decode standard, padded base64, as written by systemd.
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+
+    for chunk in encoded.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+
+        let mut bits = 0;
+        for (i, c) in chunk.iter().enumerate() {
+            bits |= value(*c)? << (18 - 6 * i);
+        }
+
+        let bytes = bits.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn decode_trigger() {
+        assert_eq!(
+            base64_decode("c29tZSAyMDAwMDAgMjAwMDAwMAA=").unwrap(),
+            b"some 200000 2000000\0"
+        );
+        assert_eq!(base64_decode("aGk=").unwrap(), b"hi");
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert!(base64_decode("a").is_none());
+        assert!(base64_decode("a*==").is_none());
+    }
+
+    #[test]
+    fn watch_socket() {
+        let path = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("systemd-pressure-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let pressure = MemoryPressure::open(&path, Some(b"some 1 2")).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let mut trigger = [0; 8];
+        server.read_exact(&mut trigger).unwrap();
+        assert_eq!(&trigger, b"some 1 2");
+
+        assert!(!pressure.wait(Some(Duration::from_millis(10))).unwrap());
+        server.write_all(b"pressure").unwrap();
+        assert!(pressure.wait(Some(Duration::from_secs(5))).unwrap());
+        assert!(!pressure.wait(Some(Duration::from_millis(10))).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
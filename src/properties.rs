@@ -1,22 +1,574 @@
 //! Access properties of systemd units via systemctl
 
-use std::{collections::HashMap, io, str::FromStr};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, ExitStatus, Output, Stdio},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
+use camino::Utf8PathBuf;
 use thiserror::Error;
 
 /// Use `systemctl show` to get properties of a systemd unit.
-pub fn properties(unit: &str) -> Result<SystemDProperties, PropertyParseError> {
-    let mut cmd = std::process::Command::new("systemctl");
+pub fn properties<U: AsRef<OsStr>>(unit: U) -> Result<SystemDProperties, PropertyParseError> {
+    properties_configured(unit, |_| {})
+}
+
+/// Use `systemctl show` to get properties of a systemd unit, configuring the command first.
+///
+/// `configure` can set environment variables (e.g. `DBUS_SESSION_BUS_ADDRESS`),
+/// the working directory, or extra arguments such as `--user`, before `systemctl` runs.
+pub fn properties_configured<U, F>(
+    unit: U,
+    configure: F,
+) -> Result<SystemDProperties, PropertyParseError>
+where
+    U: AsRef<OsStr>,
+    F: FnOnce(&mut Command),
+{
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("show");
+    cmd.arg(unit.as_ref());
+    configure(&mut cmd);
+
+    show(&mut cmd, None)
+}
+
+/// Run a `systemctl show` command, optionally with a timeout, and parse its output.
+pub(crate) fn show(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<SystemDProperties, PropertyParseError> {
+    let output = match timeout {
+        Some(timeout) => run_timeout(cmd, timeout)?,
+        None => run(cmd)?,
+    };
+    let output = succeeded(output)?;
+
+    parse_properties(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Use `systemctl show` to get the unparsed properties of a systemd unit.
+///
+/// This is for diagnosing parse failures, e.g. with a new systemd version,
+/// by showing exactly what systemctl returned.
+pub fn properties_raw<U: AsRef<OsStr>>(unit: U) -> Result<String, PropertyParseError> {
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("show");
+    cmd.arg(unit.as_ref());
+
+    let output = succeeded(run(&mut cmd)?)?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse `systemctl show` output, attaching the start of it to any error.
+fn parse_properties(output: &str) -> Result<SystemDProperties, PropertyParseError> {
+    output.parse().map_err(|err| PropertyParseError::Parse {
+        snippet: snippet(output, 512).to_owned(),
+        source: Box::new(err),
+    })
+}
+
+/// The first `len` bytes of `text`, or fewer to end on a character boundary.
+fn snippet(text: &str, len: usize) -> &str {
+    let mut end = len.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Use `systemctl -H <host> show` to get properties of a unit on a remote machine.
+///
+/// The host is given as `[user@]host`, and reached over SSH, so it must accept
+/// key-based logins non-interactively. Connection failures are reported as
+/// [PropertyParseError::CommandFailed], with SSH's message.
+pub fn properties_remote<U: AsRef<OsStr>>(
+    host: &str,
+    unit: U,
+) -> Result<SystemDProperties, PropertyParseError> {
+    properties_configured(unit, |cmd| {
+        cmd.args(["-H", host]);
+    })
+}
+
+/// Use `systemctl show` to get properties of a systemd unit, retrying while D-Bus is unavailable.
+///
+/// Early in boot, `systemctl` can fail with "Failed to connect to bus" before the
+/// service manager is ready. Such failures are retried up to `attempts` times in
+/// total, waiting `backoff` before the first retry and doubling the wait each time.
+/// Other errors, such as parse errors, are returned immediately.
+pub fn properties_retry<U: AsRef<OsStr>>(
+    unit: U,
+    attempts: usize,
+    backoff: Duration,
+) -> Result<SystemDProperties, PropertyParseError> {
+    retry(attempts, backoff, || properties(unit.as_ref()))
+}
+
+fn retry<T, F>(attempts: usize, mut backoff: Duration, mut f: F) -> Result<T, PropertyParseError>
+where
+    F: FnMut() -> Result<T, PropertyParseError>,
+{
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(err) if attempt < attempts && err.is_bus_error() => {
+                tracing::debug!("systemctl could not reach D-Bus, retrying in {backoff:?}: {err}");
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Use `systemctl show -p <name> --value` to get a single property of a systemd unit.
+///
+/// This avoids fetching and parsing every property. Empty values are returned as `None`.
+pub fn property_value<U: AsRef<OsStr>>(
+    unit: U,
+    name: &str,
+) -> Result<Option<String>, PropertyParseError> {
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("show");
+    cmd.args(["-p", name, "--value"]);
+    cmd.arg(unit.as_ref());
+
+    let output = succeeded(run(&mut cmd)?)?;
+
+    let value = String::from_utf8_lossy(&output.stdout);
+    let value = value.trim();
+    Ok((!value.is_empty()).then(|| value.to_owned()))
+}
+
+/// Use `systemctl show` to get properties of a systemd unit, giving up after `timeout`.
+///
+/// If `systemctl` has not finished by the deadline, for example because D-Bus
+/// is hung, it is killed and [PropertyParseError::Timeout] is returned.
+pub fn properties_with_timeout<U: AsRef<OsStr>>(
+    unit: U,
+    timeout: Duration,
+) -> Result<SystemDProperties, PropertyParseError> {
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("show");
+    cmd.arg(unit.as_ref());
+
+    show(&mut cmd, Some(timeout))
+}
+
+/// Wait for a unit's `MainPID` to be set, polling `systemctl show`
+///
+/// For `Type=forking` services, `MainPID` is 0 until the fork settles. This
+/// gives up with [PropertyParseError::Timeout] if it is still unset after `timeout`.
+pub fn wait_for_main_pid<U: AsRef<OsStr>>(
+    unit: U,
+    timeout: Duration,
+) -> Result<u32, PropertyParseError> {
+    poll_until(timeout, || Ok(properties(unit.as_ref())?.main_pid()))
+}
+
+/// How often [wait_for_main_pid] polls
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Call `poll` until it returns a value, giving up after `timeout`.
+fn poll_until<T, F>(timeout: Duration, mut poll: F) -> Result<T, PropertyParseError>
+where
+    F: FnMut() -> Result<Option<T>, PropertyParseError>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = poll()? {
+            return Ok(value);
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(PropertyParseError::Timeout(timeout));
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Run a systemctl command, killing it if it runs longer than `timeout`.
+fn run_timeout(cmd: &mut Command, timeout: Duration) -> Result<Output, PropertyParseError> {
+    output_timeout(cmd, timeout)
+        .map_err(command_error)?
+        .ok_or(PropertyParseError::Timeout(timeout))
+}
+
+/// Run a command, collecting its output, or killing it and returning `None` after `timeout`.
+pub(crate) fn output_timeout(cmd: &mut Command, timeout: Duration) -> io::Result<Option<Output>> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = cmd.spawn()?;
+
+    // Drain the pipes on other threads, so that a full pipe can't stall the child.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Run a systemctl command, collecting its output.
+fn run(cmd: &mut Command) -> Result<Output, PropertyParseError> {
+    run_with(cmd, Command::output)
+}
+
+/// Run a systemctl command with the given runner, distinguishing a missing `systemctl`.
+fn run_with<F>(cmd: &mut Command, runner: F) -> Result<Output, PropertyParseError>
+where
+    F: FnOnce(&mut Command) -> io::Result<Output>,
+{
+    runner(cmd).map_err(command_error)
+}
+
+/// Turn a non-zero exit into [PropertyParseError::CommandFailed].
+fn succeeded(output: Output) -> Result<Output, PropertyParseError> {
+    if !output.status.success() {
+        return Err(PropertyParseError::CommandFailed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+    Ok(output)
+}
+
+fn command_error(err: io::Error) -> PropertyParseError {
+    match err.kind() {
+        io::ErrorKind::NotFound => PropertyParseError::SystemctlNotFound,
+        _ => PropertyParseError::CommandError(err),
+    }
+}
+
+/// Use `systemctl show` to get the properties of many systemd units at once.
+///
+/// Systemctl can warn about some units, e.g. invalid names, while still exiting
+/// successfully. Those warnings are kept in [ParsedUnits::warnings], so that
+/// they can be reported alongside the units which were found.
+pub fn properties_many<I, S>(units: I) -> Result<ParsedUnits, PropertyParseError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("show");
+    cmd.args(units);
+
+    parse_units(succeeded(run(&mut cmd)?)?)
+}
+
+fn parse_units(output: Output) -> Result<ParsedUnits, PropertyParseError> {
+    let mut reader = output.stdout.as_slice();
+    let mut units = Vec::new();
+    while let Some(unit) = next_record(&mut reader) {
+        units.push(unit?);
+    }
+
+    let warnings = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect();
+
+    Ok(ParsedUnits { units, warnings })
+}
+
+/// The properties of several units, from [properties_many]
+#[derive(Debug, Clone)]
+pub struct ParsedUnits {
+    units: Vec<SystemDProperties>,
+    warnings: Vec<String>,
+}
+
+impl ParsedUnits {
+    /// The properties of each unit, in the order systemctl printed them
+    pub fn units(&self) -> &[SystemDProperties] {
+        &self.units
+    }
+
+    /// Warnings systemctl printed to stderr, one per line
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Take the properties of each unit, discarding any warnings
+    pub fn into_units(self) -> Vec<SystemDProperties> {
+        self.units
+    }
+}
+
+/// Use `systemctl show` to stream the properties of many systemd units.
+///
+/// Properties are parsed one unit at a time as `systemctl` writes them, rather
+/// than buffering the whole output, which keeps memory bounded for bulk queries.
+pub fn properties_stream<I, S>(units: I) -> Result<PropertiesStream, PropertyParseError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new("systemctl");
     cmd.arg("show");
-    cmd.arg(unit);
+    cmd.args(units);
+
+    PropertiesStream::spawn(&mut cmd)
+}
+
+/// An iterator over the properties of units, read from a running `systemctl show`
+///
+/// Once the output is exhausted, a non-zero exit from `systemctl` is reported as
+/// a final [PropertyParseError::CommandFailed]. Dropping the stream stops
+/// `systemctl` if it is still running.
+#[derive(Debug)]
+pub struct PropertiesStream {
+    child: Child,
+    reader: BufReader<ChildStdout>,
+    stderr: Option<thread::JoinHandle<Vec<u8>>>,
+}
+
+impl PropertiesStream {
+    fn spawn(cmd: &mut Command) -> Result<Self, PropertyParseError> {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        let mut child = cmd.spawn().map_err(command_error)?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = drain(child.stderr.take());
+
+        Ok(PropertiesStream {
+            child,
+            reader: BufReader::new(stdout),
+            stderr: Some(stderr),
+        })
+    }
+
+    /// Wait for `systemctl` to exit, turning a failure into an error.
+    fn finish(&mut self, stderr: thread::JoinHandle<Vec<u8>>) -> Option<PropertyParseError> {
+        let status = match self.child.wait() {
+            Ok(status) => status,
+            Err(err) => return Some(err.into()),
+        };
+        let stderr = stderr.join().unwrap_or_default();
+
+        succeeded(Output {
+            status,
+            stdout: Vec::new(),
+            stderr,
+        })
+        .err()
+    }
+}
+
+impl Iterator for PropertiesStream {
+    type Item = Result<SystemDProperties, PropertyParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Once systemctl has been waited for, the stream is over.
+        let stderr = self.stderr.take()?;
+
+        match next_record(&mut self.reader) {
+            Some(record) => {
+                self.stderr = Some(stderr);
+                Some(record)
+            }
+            None => self.finish(stderr).map(Err),
+        }
+    }
+}
+
+impl Drop for PropertiesStream {
+    fn drop(&mut self) {
+        if self.stderr.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Watch a unit's active state, polling `systemctl` every `interval`
+///
+/// The watch is a [Stream](futures_core::Stream) of state changes, see
+/// [StateWatch::next] for how they are reported. This must be used from within
+/// a tokio runtime.
+#[cfg(feature = "tokio")]
+pub fn watch_state<U: AsRef<OsStr>>(unit: U, interval: Duration) -> StateWatch {
+    StateWatch {
+        unit: unit.as_ref().to_owned(),
+        interval,
+        last: None,
+        polled: false,
+        pending: None,
+    }
+}
+
+/// A poll of a unit's active state, after an optional delay
+#[cfg(feature = "tokio")]
+type StatePoll = std::pin::Pin<
+    Box<dyn std::future::Future<Output = Result<ActiveState, PropertyParseError>> + Send>,
+>;
+
+/// A watch on a unit's active state, from [watch_state]
+///
+/// As a stream, this never ends: a failed poll is yielded as an error, and the
+/// next poll is attempted after the interval.
+#[cfg(feature = "tokio")]
+pub struct StateWatch {
+    unit: std::ffi::OsString,
+    interval: Duration,
+    last: Option<ActiveState>,
+    polled: bool,
+    pending: Option<StatePoll>,
+}
+
+#[cfg(feature = "tokio")]
+impl fmt::Debug for StateWatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateWatch")
+            .field("unit", &self.unit)
+            .field("interval", &self.interval)
+            .field("last", &self.last)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl StateWatch {
+    /// Wait for the unit's active state to change
+    ///
+    /// The first call returns the current state immediately. Later calls poll
+    /// until the state differs from the one last returned, so transitions which
+    /// are quicker than the polling interval may be missed.
+    pub async fn next(&mut self) -> Result<ActiveState, PropertyParseError> {
+        std::future::poll_fn(|cx| self.poll_change(cx)).await
+    }
+
+    fn poll_change(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<ActiveState, PropertyParseError>> {
+        loop {
+            if self.pending.is_none() {
+                let delay = self.polled.then_some(self.interval);
+                self.pending = Some(Box::pin(poll_state(self.unit.clone(), delay)));
+                self.polled = true;
+            }
+            let pending = self.pending.as_mut().expect("poll is pending");
+
+            let state = std::task::ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+
+            if let Some(state) = self.changed(state?) {
+                return std::task::Poll::Ready(Ok(state));
+            }
+        }
+    }
+
+    /// Record a polled state, returning it if it differs from the last.
+    fn changed(&mut self, state: ActiveState) -> Option<ActiveState> {
+        if self.last == Some(state) {
+            return None;
+        }
+        self.last = Some(state);
+        Some(state)
+    }
+}
 
-    let output = cmd.output()?;
+#[cfg(feature = "tokio")]
+impl futures_core::Stream for StateWatch {
+    type Item = Result<ActiveState, PropertyParseError>;
 
-    String::from_utf8(output.stdout).unwrap().parse()
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.poll_change(cx).map(Some)
+    }
+}
+
+/// Read a unit's active state on the blocking pool, after waiting for `delay`.
+#[cfg(feature = "tokio")]
+async fn poll_state(
+    unit: std::ffi::OsString,
+    delay: Option<Duration>,
+) -> Result<ActiveState, PropertyParseError> {
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    let state = tokio::task::spawn_blocking(move || property_value(unit, "ActiveState"))
+        .await
+        .map_err(io::Error::from)??
+        .ok_or(PropertyParseError::MissingProperty("ActiveState"))?
+        .parse()?;
+    Ok(state)
+}
+
+/// Read the next blank-line delimited record of properties.
+fn next_record<R: BufRead>(
+    reader: &mut R,
+) -> Option<Result<SystemDProperties, PropertyParseError>> {
+    let mut record = String::new();
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        // Values aren't always UTF-8, e.g. a Description=, so decode each line lossily.
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line);
+                if !line.trim().is_empty() {
+                    record.push_str(&line);
+                } else if !record.is_empty() {
+                    break;
+                }
+            }
+            Err(err) => return Some(Err(err.into())),
+        }
+    }
+
+    if record.is_empty() {
+        return None;
+    }
+    Some(record.parse())
 }
 
 /// The active state of a systemd unit
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ActiveState {
     /// The service is active and responding
     Active,
@@ -35,6 +587,12 @@ pub enum ActiveState {
 
     /// The service is deactivating - it has stopped but is not yet inactive
     Deactivating,
+
+    /// The unit is inactive while a maintenance operation is in progress
+    Maintenance,
+
+    /// The unit is running while new mount points are attached to it
+    Refreshing,
 }
 
 /// Errors that can occur when parsing a systemd unit's properties
@@ -53,6 +611,194 @@ impl FromStr for ActiveState {
             "failed" => Ok(Failed),
             "activating" => Ok(Activating),
             "deactivating" => Ok(Deactivating),
+            "maintenance" => Ok(Maintenance),
+            "refreshing" => Ok(Refreshing),
+            _ => Err(StateParseError(s.into())),
+        }
+    }
+}
+
+/// Use `systemctl is-enabled` to get the install state of a systemd unit.
+pub fn is_enabled<U: AsRef<OsStr>>(unit: U) -> Result<EnableState, PropertyParseError> {
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("is-enabled");
+    cmd.arg(unit.as_ref());
+
+    enable_state(unit.as_ref(), run(&mut cmd)?)
+}
+
+/// Interpret the output of `systemctl is-enabled` for `unit`.
+///
+/// The exit code is non-zero for several valid states, so a state on stdout is
+/// used regardless. Without one, the command failed, e.g. for a missing unit.
+fn enable_state(unit: &OsStr, output: Output) -> Result<EnableState, PropertyParseError> {
+    let state = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if state.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("No such file or directory") || stderr.contains("not found") {
+            return Err(PropertyParseError::UnitNotFound(
+                unit.to_string_lossy().into_owned(),
+            ));
+        }
+        succeeded(output)?;
+    }
+
+    Ok(state.parse()?)
+}
+
+/// Use `systemctl list-units` to list all units, optionally matching a glob pattern.
+pub fn list_units(pattern: Option<&str>) -> Result<Vec<UnitListing>, PropertyParseError> {
+    let mut cmd = Command::new("systemctl");
+    cmd.args([
+        "list-units",
+        "--all",
+        "--no-legend",
+        "--plain",
+        "--no-pager",
+    ]);
+    if let Some(pattern) = pattern {
+        cmd.arg(pattern);
+    }
+
+    let output = succeeded(run(&mut cmd)?)?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// A single unit from `systemctl list-units`
+#[derive(Debug, Clone)]
+pub struct UnitListing {
+    unit: String,
+    load: String,
+    active: ActiveState,
+    sub: String,
+    description: String,
+}
+
+impl UnitListing {
+    /// The name of the unit
+    pub fn unit(&self) -> &str {
+        &self.unit
+    }
+
+    /// Whether the unit definition was properly loaded, e.g. `loaded` or `not-found`
+    pub fn load(&self) -> &str {
+        &self.load
+    }
+
+    /// The high-level activation state of the unit
+    pub fn active(&self) -> ActiveState {
+        self.active
+    }
+
+    /// The low-level, unit type specific activation state, e.g. `running` or `exited`
+    pub fn sub(&self) -> &str {
+        &self.sub
+    }
+
+    /// The description of the unit
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl FromStr for UnitListing {
+    type Err = PropertyParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PropertyParseError::InvalidListing(s.into());
+
+        // The first four columns never contain whitespace, but the description can.
+        let mut rest = s.trim();
+        let mut columns = [""; 4];
+        for column in &mut columns {
+            let (value, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if value.is_empty() {
+                return Err(invalid());
+            }
+            *column = value;
+            rest = remainder.trim_start();
+        }
+        let [unit, load, active, sub] = columns;
+
+        Ok(UnitListing {
+            unit: unit.into(),
+            load: load.into(),
+            active: active.parse()?,
+            sub: sub.into(),
+            description: rest.into(),
+        })
+    }
+}
+
+/// The install (enablement) state of a systemd unit, from `systemctl is-enabled`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableState {
+    /// Enabled via symlinks in `.wants/`, `.requires/` or an alias
+    Enabled,
+
+    /// Enabled only until the next reboot
+    EnabledRuntime,
+
+    /// Made available through a symlink to the unit file
+    Linked,
+
+    /// Linked only until the next reboot
+    LinkedRuntime,
+
+    /// The name is an alias of another unit
+    Alias,
+
+    /// Completely disabled, so that any start operation fails
+    Masked,
+
+    /// Masked only until the next reboot
+    MaskedRuntime,
+
+    /// The unit file has no install section, and can't be enabled
+    Static,
+
+    /// The unit file is not enabled, but is referenced by another unit
+    Indirect,
+
+    /// The unit file is not enabled
+    Disabled,
+
+    /// The unit file was generated dynamically by a generator
+    Generated,
+
+    /// The unit file was created dynamically with the runtime API
+    Transient,
+
+    /// The unit file is invalid
+    Bad,
+
+    /// The unit file doesn't exist
+    NotFound,
+}
+
+impl FromStr for EnableState {
+    type Err = StateParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use EnableState::*;
+        match s {
+            "enabled" => Ok(Enabled),
+            "enabled-runtime" => Ok(EnabledRuntime),
+            "linked" => Ok(Linked),
+            "linked-runtime" => Ok(LinkedRuntime),
+            "alias" => Ok(Alias),
+            "masked" => Ok(Masked),
+            "masked-runtime" => Ok(MaskedRuntime),
+            "static" => Ok(Static),
+            "indirect" => Ok(Indirect),
+            "disabled" => Ok(Disabled),
+            "generated" => Ok(Generated),
+            "transient" => Ok(Transient),
+            "bad" => Ok(Bad),
+            "not-found" => Ok(NotFound),
             _ => Err(StateParseError(s.into())),
         }
     }
@@ -69,19 +815,62 @@ pub enum PropertyParseError {
     #[error("Line {0} is missing the delimiter '='")]
     MissingDelimeter(String),
 
+    /// A line of `systemctl list-units` output could not be parsed
+    #[error("Line {0} is not a valid unit listing")]
+    InvalidListing(String),
+
     /// An expected property is missing
     #[error("Missing property {0}")]
     MissingProperty(&'static str),
 
+    /// Systemd has no unit with the given name
+    #[error("Unit {0} was not found")]
+    UnitNotFound(String),
+
+    /// The systemctl command is not installed
+    #[error("systemctl was not found")]
+    SystemctlNotFound,
+
+    /// Systemctl ran, but reported a failure
+    #[error("systemctl failed ({status}): {stderr}")]
+    CommandFailed {
+        /// The exit status of systemctl
+        status: ExitStatus,
+
+        /// What systemctl wrote to stderr
+        stderr: String,
+    },
+
+    /// Systemctl did not finish before the timeout elapsed
+    #[error("systemctl timed out after {0:?}")]
+    Timeout(Duration),
+
     /// A command error occured running systemctl
     #[error("Running systemctl: {0}")]
     CommandError(#[from] io::Error),
+
+    /// The output of `systemctl show` could not be parsed
+    #[error("Parsing systemctl show output: {source}")]
+    Parse {
+        /// The start of the output, for diagnosing the failure
+        snippet: String,
+
+        /// Why the output could not be parsed
+        source: Box<PropertyParseError>,
+    },
+}
+
+impl PropertyParseError {
+    /// Whether systemctl failed because it could not connect to D-Bus, which may be transient
+    pub fn is_bus_error(&self) -> bool {
+        matches!(self, PropertyParseError::CommandFailed { stderr, .. } if stderr.contains("Failed to connect to bus"))
+    }
 }
 
 /// A map of systemd properties
 #[derive(Debug, Clone)]
 pub struct SystemDProperties {
-    properties: HashMap<String, String>,
+    properties: HashMap<String, Vec<String>>,
     active: ActiveState,
 }
 
@@ -92,30 +881,1516 @@ impl SystemDProperties {
     }
 
     /// Get a property of the systemd unit
+    ///
+    /// If systemctl repeated the property, this is the last value.
     pub fn property(&self, name: &str) -> Option<&str> {
-        self.properties.get(name).map(|s| s.as_str())
+        self.properties
+            .get(name)
+            .and_then(|values| values.last())
+            .map(|s| s.as_str())
     }
-}
 
-impl FromStr for SystemDProperties {
-    type Err = PropertyParseError;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut properties = HashMap::new();
+    /// Get every value of a property which systemctl repeats, such as `ExecStartPre`
+    pub fn property_all(&self, name: &str) -> Vec<&str> {
+        self.properties
+            .get(name)
+            .map(|values| values.iter().map(|s| s.as_str()).collect())
+            .unwrap_or_default()
+    }
 
-        for line in s.lines() {
-            let (key, value) = line
-                .trim()
-                .split_once('=')
-                .ok_or_else(|| PropertyParseError::MissingDelimeter(line.into()))?;
+    /// Summarize the unit's load, active and sub states, and description
+    pub fn status(&self) -> UnitStatus {
+        let owned = |name| self.property(name).unwrap_or_default().to_owned();
 
-            properties.insert(key.to_owned(), value.to_owned());
+        UnitStatus {
+            active: self.active,
+            load: owned("LoadState"),
+            sub: owned("SubState"),
+            description: owned("Description"),
         }
+    }
 
-        let active = properties
-            .get("ActiveState")
-            .ok_or(PropertyParseError::MissingProperty("ActiveState"))?
-            .parse()?;
+    /// The PID of the unit's main process, if it has one
+    pub fn main_pid(&self) -> Option<u32> {
+        self.property("MainPID")?
+            .parse()
+            .ok()
+            .filter(|pid| *pid != 0)
+    }
 
-        Ok(Self { properties, active })
+    /// The number of times systemd has automatically restarted the unit
+    pub fn n_restarts(&self) -> Option<u64> {
+        self.property("NRestarts")?.parse().ok()
+    }
+
+    /// The number of tasks (processes and threads) currently in the unit
+    pub fn tasks_current(&self) -> Option<u64> {
+        self.limit("TasksCurrent")
+    }
+
+    /// The maximum number of tasks the unit may create, or `None` if unlimited
+    pub fn tasks_max(&self) -> Option<u64> {
+        self.limit("TasksMax")
+    }
+
+    /// A counter or limit, where `infinity` and `u64::MAX` (unset) are `None`.
+    fn limit(&self, name: &str) -> Option<u64> {
+        match self.property(name)? {
+            "infinity" => None,
+            value => value.parse().ok().filter(|value| *value != u64::MAX),
+        }
+    }
+
+    /// Whether the unit's `Condition*=` checks passed when it was last started
+    ///
+    /// A failed condition skips the unit, leaving it inactive without an error.
+    pub fn condition_result(&self) -> Option<bool> {
+        self.boolean("ConditionResult")
+    }
+
+    /// Whether the unit's `Assert*=` checks passed when it was last started
+    pub fn assert_result(&self) -> Option<bool> {
+        self.boolean("AssertResult")
+    }
+
+    /// Whether memory accounting (`MemoryAccounting=`) is on, so `MemoryCurrent` is meaningful
+    pub fn memory_accounting(&self) -> Option<bool> {
+        self.boolean("MemoryAccounting")
+    }
+
+    /// Whether CPU accounting (`CPUAccounting=`) is on, so `CPUUsageNSec` is meaningful
+    pub fn cpu_accounting(&self) -> Option<bool> {
+        self.boolean("CPUAccounting")
+    }
+
+    /// Whether task accounting (`TasksAccounting=`) is on, so `TasksCurrent` is meaningful
+    pub fn tasks_accounting(&self) -> Option<bool> {
+        self.boolean("TasksAccounting")
+    }
+
+    fn boolean(&self, name: &str) -> Option<bool> {
+        match self.property(name)? {
+            "yes" => Some(true),
+            "no" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The environment variables set for the unit by `Environment=`
+    ///
+    /// Entries without a `=` are skipped. Values may themselves contain `=`.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        split_words(self.property("Environment").unwrap_or_default())
+            .into_iter()
+            .filter_map(|word| {
+                let (key, value) = word.split_once('=')?;
+                Some((key.to_owned(), value.to_owned()))
+            })
+            .collect()
+    }
+
+    /// The commands the unit runs to start, from its `ExecStart=` lines
+    pub fn exec_start(&self) -> Vec<ExecCommand> {
+        self.property_all("ExecStart")
+            .into_iter()
+            .flat_map(parse_exec_commands)
+            .collect()
+    }
+
+    /// The path of the unit file which defines this unit
+    pub fn fragment_path(&self) -> Option<Utf8PathBuf> {
+        self.property("FragmentPath")
+            .filter(|path| !path.is_empty())
+            .map(Utf8PathBuf::from)
+    }
+
+    /// The paths of drop-in files which override parts of the unit file
+    pub fn drop_in_paths(&self) -> Vec<Utf8PathBuf> {
+        self.property("DropInPaths")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(Utf8PathBuf::from)
+            .collect()
+    }
+
+    /// The path and contents of each drop-in file, in the order systemd applies them
+    ///
+    /// This shows which snippet sets which directive, e.g. for a configuration
+    /// audit. Files which have disappeared or can't be read, e.g. without root,
+    /// are skipped with a warning.
+    pub fn effective_drop_ins(&self) -> io::Result<Vec<(Utf8PathBuf, String)>> {
+        let mut drop_ins = Vec::new();
+        for path in self.drop_in_paths() {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => drop_ins.push((path, contents)),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                    ) =>
+                {
+                    tracing::warn!("Skipping drop-in {path}: {err}");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(drop_ins)
+    }
+
+    /// The units this unit has a dependency of the given kind on
+    pub fn dependencies(&self, kind: DependencyKind) -> Vec<&str> {
+        self.property(kind.property())
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect()
+    }
+
+    /// The units which activate this one, such as the `.socket` unit of a socket activated service
+    pub fn triggered_by(&self) -> Vec<&str> {
+        self.dependencies(DependencyKind::TriggeredBy)
+    }
+
+    /// The units which this one activates, such as the service of a `.socket` unit
+    pub fn triggers(&self) -> Vec<&str> {
+        self.dependencies(DependencyKind::Triggers)
+    }
+
+    /// The service's `Type=`, which determines how it signals readiness
+    ///
+    /// This is `None` for units which are not services.
+    pub fn service_type(&self) -> Option<ServiceType> {
+        self.property("Type")?.parse().ok()
+    }
+
+    /// How long ago the unit last entered the active state
+    ///
+    /// This is computed from `ActiveEnterTimestampMonotonic`, so it is unaffected
+    /// by changes to the wall clock. It is `None` if the unit has never been active.
+    pub fn active_enter_ago(&self) -> Option<Duration> {
+        self.monotonic_ago("ActiveEnterTimestampMonotonic")
+    }
+
+    /// How long ago the unit last entered the inactive state
+    ///
+    /// See [SystemDProperties::active_enter_ago].
+    pub fn inactive_enter_ago(&self) -> Option<Duration> {
+        self.monotonic_ago("InactiveEnterTimestampMonotonic")
+    }
+
+    /// When the unit's `Condition*=` checks were last evaluated
+    ///
+    /// Together with [SystemDProperties::condition_result], this tells when a start
+    /// was skipped. This reads the wall clock `ConditionTimestamp`, which is only
+    /// machine readable when the properties were shown with `--timestamp=unix`, e.g.
+    /// using [properties_configured]. Otherwise, `None` is returned.
+    pub fn condition_timestamp(&self) -> Option<SystemTime> {
+        let secs = self.property("ConditionTimestamp")?.strip_prefix('@')?;
+        SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs.parse().ok()?))
+    }
+
+    fn monotonic_ago(&self, name: &str) -> Option<Duration> {
+        let usec = self
+            .property(name)?
+            .parse()
+            .ok()
+            .filter(|usec| *usec != 0)?;
+        crate::monotonic_now()
+            .ok()?
+            .checked_sub(Duration::from_micros(usec))
+    }
+
+    /// How long systemd waits for the unit to start, or `None` if it waits indefinitely
+    pub fn timeout_start(&self) -> Option<Duration> {
+        parse_systemd_duration(self.property("TimeoutStartUSec")?)
+    }
+
+    /// How long systemd waits for the unit to stop, or `None` if it waits indefinitely
+    pub fn timeout_stop(&self) -> Option<Duration> {
+        parse_systemd_duration(self.property("TimeoutStopUSec")?)
+    }
+
+    /// Which of the service's processes may send notifications, from `NotifyAccess=`
+    ///
+    /// Notifications from other processes are dropped silently by systemd.
+    pub fn notify_access(&self) -> Option<NotifyAccess> {
+        self.property("NotifyAccess")?.parse().ok()
+    }
+
+    /// What systemd does when the kernel OOM killer kills one of the unit's processes, from `OOMPolicy=`
+    pub fn oom_policy(&self) -> Option<OomPolicy> {
+        self.property("OOMPolicy").map(OomPolicy::from)
+    }
+
+    /// The adjustment to the OOM killer score of the unit's processes, from `OOMScoreAdjust=`
+    ///
+    /// This ranges from -1000 (never kill) to 1000 (kill first).
+    pub fn oom_score_adjust(&self) -> Option<i32> {
+        self.property("OOMScoreAdjust")?.parse().ok()
+    }
+
+    /// Why the unit last stopped or failed, from the `Result` property
+    pub fn result(&self) -> Option<UnitResult> {
+        self.property("Result").map(UnitResult::from)
+    }
+
+    /// The slice the unit runs under, e.g. `system-myapp.slice`
+    pub fn slice(&self) -> Option<&str> {
+        self.property("Slice").filter(|slice| !slice.is_empty())
+    }
+
+    /// The path of the unit's control group, relative to the cgroup root
+    ///
+    /// This is `None` for units which are not running.
+    pub fn control_group(&self) -> Option<&str> {
+        self.property("ControlGroup")
+            .filter(|cgroup| !cgroup.is_empty())
+    }
+
+    /// Read a file, such as `memory.current`, from the unit's cgroup v2 directory
+    ///
+    /// Returns `None` if the unit has no control group, if the unified (v2)
+    /// hierarchy is not mounted at `/sys/fs/cgroup`, or if the file does not exist
+    /// or can't be read, as in containers which restrict `/sys`.
+    pub fn cgroup_file(&self, name: &str) -> io::Result<Option<String>> {
+        match self.control_group() {
+            Some(cgroup) => read_cgroup_file(Path::new(CGROUP_ROOT), cgroup, name),
+            None => Ok(None),
+        }
+    }
+
+    /// Read a flat-keyed cgroup file, such as `cpu.stat`, into a map of counters
+    ///
+    /// See [SystemDProperties::cgroup_file] for when this returns `None`.
+    pub fn cgroup_stat(&self, name: &str) -> io::Result<Option<HashMap<String, u64>>> {
+        Ok(self
+            .cgroup_file(name)?
+            .map(|contents| parse_keyed(&contents)))
+    }
+}
+
+/// A kind of dependency between units, for [SystemDProperties::dependencies]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// Units started along with this one (`Wants=`)
+    Wants,
+
+    /// Units which must start for this one to start (`Requires=`)
+    Requires,
+
+    /// Units which must already be active for this one to start (`Requisite=`)
+    Requisite,
+
+    /// Units whose stop or restart also applies to this one (`PartOf=`)
+    PartOf,
+
+    /// Units this one is ordered after (`After=`)
+    After,
+
+    /// Units this one is ordered before (`Before=`)
+    Before,
+
+    /// Units which activate this one, e.g. its `.socket` unit
+    TriggeredBy,
+
+    /// Units this one activates, e.g. a socket's service
+    Triggers,
+}
+
+impl DependencyKind {
+    fn property(self) -> &'static str {
+        match self {
+            DependencyKind::Wants => "Wants",
+            DependencyKind::Requires => "Requires",
+            DependencyKind::Requisite => "Requisite",
+            DependencyKind::PartOf => "PartOf",
+            DependencyKind::After => "After",
+            DependencyKind::Before => "Before",
+            DependencyKind::TriggeredBy => "TriggeredBy",
+            DependencyKind::Triggers => "Triggers",
+        }
+    }
+}
+
+/// The startup type of a service unit, from `Type=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceType {
+    /// The service is ready as soon as its main process has been forked
+    Simple,
+
+    /// The service is ready once its main process has been executed
+    Exec,
+
+    /// The service is ready once its main process forks and the parent exits
+    Forking,
+
+    /// The service is ready once its main process exits
+    Oneshot,
+
+    /// The service is ready once it acquires its D-Bus name
+    Dbus,
+
+    /// The service is ready once it sends `READY=1`
+    Notify,
+
+    /// Like [ServiceType::Notify], and also signals reloads with `RELOADING=1`
+    NotifyReload,
+
+    /// Like [ServiceType::Simple], but delayed until other jobs are dispatched
+    Idle,
+}
+
+impl ServiceType {
+    /// Whether systemd waits for a `READY=1` notification from this service
+    pub fn expects_ready(&self) -> bool {
+        matches!(self, ServiceType::Notify | ServiceType::NotifyReload)
+    }
+}
+
+impl FromStr for ServiceType {
+    type Err = StateParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ServiceType::*;
+        match s {
+            "simple" => Ok(Simple),
+            "exec" => Ok(Exec),
+            "forking" => Ok(Forking),
+            "oneshot" => Ok(Oneshot),
+            "dbus" => Ok(Dbus),
+            "notify" => Ok(Notify),
+            "notify-reload" => Ok(NotifyReload),
+            "idle" => Ok(Idle),
+            _ => Err(StateParseError(s.into())),
+        }
+    }
+}
+
+/// Which processes of a service may send notifications, from `NotifyAccess=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyAccess {
+    /// All notifications are ignored
+    None,
+
+    /// Only the main process may send notifications
+    Main,
+
+    /// The main process and processes run by `Exec*=` commands may send notifications
+    Exec,
+
+    /// Every process in the service's control group may send notifications
+    All,
+}
+
+impl NotifyAccess {
+    /// Whether notifications from any process of the service, e.g. a forked helper, are accepted
+    pub fn accepts_any_process(&self) -> bool {
+        matches!(self, NotifyAccess::All)
+    }
+}
+
+impl FromStr for NotifyAccess {
+    type Err = StateParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(NotifyAccess::None),
+            "main" => Ok(NotifyAccess::Main),
+            "exec" => Ok(NotifyAccess::Exec),
+            "all" => Ok(NotifyAccess::All),
+            _ => Err(StateParseError(s.into())),
+        }
+    }
+}
+
+/// The reaction to an OOM kill in a unit, from [SystemDProperties::oom_policy]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// The unit keeps running, and the OOM kill is only logged
+    Continue,
+
+    /// The unit is stopped cleanly
+    Stop,
+
+    /// The unit's remaining processes are killed
+    Kill,
+
+    /// Any other policy
+    Other(String),
+}
+
+impl From<&str> for OomPolicy {
+    fn from(value: &str) -> Self {
+        match value {
+            "continue" => OomPolicy::Continue,
+            "stop" => OomPolicy::Stop,
+            "kill" => OomPolicy::Kill,
+            other => OomPolicy::Other(other.into()),
+        }
+    }
+}
+
+impl fmt::Display for OomPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OomPolicy::Continue => "continue",
+            OomPolicy::Stop => "stop",
+            OomPolicy::Kill => "kill",
+            OomPolicy::Other(other) => other,
+        })
+    }
+}
+
+/// The outcome of a unit's last run, from [SystemDProperties::result]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnitResult {
+    /// The unit ran successfully
+    Success,
+
+    /// The service didn't follow its protocol, e.g. a `Type=forking` service didn't fork
+    Protocol,
+
+    /// A start, stop or runtime timeout elapsed
+    Timeout,
+
+    /// The main process exited with a non-zero exit code
+    ExitCode,
+
+    /// The main process was killed by a signal
+    Signal,
+
+    /// The main process dumped core
+    CoreDump,
+
+    /// The watchdog was not pinged in time
+    Watchdog,
+
+    /// The unit was started too often, and hit its start rate limit
+    StartLimitHit,
+
+    /// A resource, e.g. a process or file descriptor, could not be allocated
+    Resources,
+
+    /// The unit was killed by the OOM killer
+    OomKill,
+
+    /// Any other result
+    Other(String),
+}
+
+impl From<&str> for UnitResult {
+    fn from(value: &str) -> Self {
+        use UnitResult::*;
+        match value {
+            "success" => Success,
+            "protocol" => Protocol,
+            "timeout" => Timeout,
+            "exit-code" => ExitCode,
+            "signal" => Signal,
+            "core-dump" => CoreDump,
+            "watchdog" => Watchdog,
+            "start-limit-hit" => StartLimitHit,
+            "resources" => Resources,
+            "oom-kill" => OomKill,
+            other => Other(other.into()),
+        }
+    }
+}
+
+impl fmt::Display for UnitResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use UnitResult::*;
+        f.write_str(match self {
+            Success => "success",
+            Protocol => "protocol",
+            Timeout => "timeout",
+            ExitCode => "exit-code",
+            Signal => "signal",
+            CoreDump => "core-dump",
+            Watchdog => "watchdog",
+            StartLimitHit => "start-limit-hit",
+            Resources => "resources",
+            OomKill => "oom-kill",
+            Other(other) => other,
+        })
+    }
+}
+
+/// A summary of a unit's state, from [SystemDProperties::status]
+#[derive(Debug, Clone)]
+pub struct UnitStatus {
+    active: ActiveState,
+    load: String,
+    sub: String,
+    description: String,
+}
+
+impl UnitStatus {
+    /// The high-level activation state of the unit
+    pub fn active(&self) -> ActiveState {
+        self.active
+    }
+
+    /// Whether the unit definition was properly loaded, e.g. `loaded` or `not-found`
+    pub fn load(&self) -> &str {
+        &self.load
+    }
+
+    /// The low-level, unit type specific activation state, e.g. `running` or `exited`
+    pub fn sub(&self) -> &str {
+        &self.sub
+    }
+
+    /// The description of the unit
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// A command which a unit runs, from a property such as [SystemDProperties::exec_start]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecCommand {
+    path: PathBuf,
+    argv: Vec<String>,
+    flags: Vec<String>,
+}
+
+impl ExecCommand {
+    /// The path of the executable
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The arguments, including `argv[0]`
+    pub fn argv(&self) -> &[String] {
+        &self.argv
+    }
+
+    /// The command's flags, e.g. `ignore-failure` for a command prefixed with `-`
+    pub fn flags(&self) -> &[String] {
+        &self.flags
+    }
+}
+
+/// Parse the commands in an exec property, such as
+/// `{ path=/usr/bin/foo ; argv[]=foo --bar ; ignore_errors=no ; ... }`.
+///
+/// A value may hold several `{ ... }` records. Records without a path are skipped.
+fn parse_exec_commands(value: &str) -> Vec<ExecCommand> {
+    let mut commands = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find(" }").map(|end| start + end) else {
+            break;
+        };
+
+        let mut path = None;
+        let mut argv = Vec::new();
+        let mut flags = Vec::new();
+        for field in rest[start + 1..end].split(" ; ") {
+            match field.trim().split_once('=') {
+                Some(("path", value)) => path = Some(PathBuf::from(value)),
+                Some(("argv[]", value)) => argv = split_words(value),
+                Some(("flags", value)) => flags.extend(value.split_whitespace().map(str::to_owned)),
+                Some(("ignore_errors", "yes")) => flags.push("ignore-failure".to_owned()),
+                _ => {}
+            }
+        }
+
+        if let Some(path) = path {
+            commands.push(ExecCommand { path, argv, flags });
+        }
+        rest = &rest[end + 2..];
+    }
+
+    commands
+}
+
+/// Format a duration as systemd does, e.g. `3min 2s` or `1d 4h`
+///
+/// Durations of a second or more are shown to the second, and shorter ones to the
+/// millisecond. This matches the spans shown by `systemctl status`.
+pub fn format_timespan(duration: Duration) -> String {
+    const UNITS: [(&str, u64); 7] = [
+        ("y", 31_557_600),
+        ("month", 2_629_800),
+        ("w", 604_800),
+        ("d", 86_400),
+        ("h", 3_600),
+        ("min", 60),
+        ("s", 1),
+    ];
+
+    let mut seconds = duration.as_secs();
+    if seconds == 0 {
+        return match duration.as_millis() {
+            0 => "0".to_owned(),
+            millis => format!("{millis}ms"),
+        };
+    }
+
+    let mut parts = Vec::new();
+    for (unit, size) in UNITS {
+        if seconds >= size {
+            parts.push(format!("{}{unit}", seconds / size));
+            seconds %= size;
+        }
+    }
+    parts.join(" ")
+}
+
+/// Parse a duration in systemd's spelling, e.g. `1h 30min`, `500ms` or `2.5s`
+///
+/// This is the format of `*USec` properties such as `TimeoutStartUSec`, and of
+/// [format_timespan]. A bare number is in seconds. Returns `None` for `infinity`,
+/// and for values which are not valid durations.
+pub fn parse_systemd_duration(s: &str) -> Option<Duration> {
+    const USEC_PER_SEC: u64 = 1_000_000;
+
+    let s = s.trim();
+    if s.is_empty() || s == "infinity" {
+        return None;
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let tail = tail.trim_start();
+        let unit_len = tail
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let usec: u64 = match unit {
+            "us" | "usec" | "µs" => 1,
+            "ms" | "msec" => 1_000,
+            "" | "s" | "sec" | "second" | "seconds" => USEC_PER_SEC,
+            "m" | "min" | "minute" | "minutes" => 60 * USEC_PER_SEC,
+            "h" | "hr" | "hour" | "hours" => 3_600 * USEC_PER_SEC,
+            "d" | "day" | "days" => 86_400 * USEC_PER_SEC,
+            "w" | "week" | "weeks" => 604_800 * USEC_PER_SEC,
+            "M" | "month" | "months" => 2_629_800 * USEC_PER_SEC,
+            "y" | "year" | "years" => 31_557_600 * USEC_PER_SEC,
+            _ => return None,
+        };
+
+        let part = match number.split_once('.') {
+            None => Duration::from_micros(number.parse::<u64>().ok()?.checked_mul(usec)?),
+            Some(_) => Duration::try_from_secs_f64(
+                number.parse::<f64>().ok()? * usec as f64 / USEC_PER_SEC as f64,
+            )
+            .ok()?,
+        };
+        total = total.checked_add(part)?;
+        rest = tail.trim_start();
+    }
+
+    Some(total)
+}
+
+/// Split a property value into words, following systemd's quoting rules.
+///
+/// Words are separated by whitespace, and may be quoted with `"` or `'` to
+/// include whitespace. C-style escapes (e.g. `\n`, `\"` or `\x41`) are decoded.
+/// A `\x` escape is a single byte, so multi-byte characters can be escaped as
+/// a sequence of them, e.g. `\xc3\xa9`.
+fn split_words(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = Vec::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some('n') => word.push(b'\n'),
+                    Some('t') => word.push(b'\t'),
+                    Some('r') => word.push(b'\r'),
+                    Some('s') => word.push(b' '),
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).collect();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(byte) => word.push(byte),
+                            Err(_) => {
+                                word.extend_from_slice(b"\\x");
+                                word.extend_from_slice(hex.as_bytes());
+                            }
+                        }
+                    }
+                    Some(other) => push_char(&mut word, other),
+                    None => word.push(b'\\'),
+                }
+            }
+            '"' | '\'' if quote == Some(c) => quote = None,
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                in_word = true;
+            }
+            c if c.is_whitespace() && quote.is_none() => {
+                if in_word {
+                    words.push(String::from_utf8_lossy(&word).into_owned());
+                    word.clear();
+                    in_word = false;
+                }
+            }
+            c => {
+                push_char(&mut word, c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        words.push(String::from_utf8_lossy(&word).into_owned());
+    }
+    words
+}
+
+fn push_char(word: &mut Vec<u8>, c: char) {
+    word.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// Where the unified cgroup hierarchy is mounted
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+fn read_cgroup_file(root: &Path, cgroup: &str, name: &str) -> io::Result<Option<String>> {
+    // Only the unified hierarchy has a cgroup.controllers file at its root.
+    if !root.join("cgroup.controllers").exists() {
+        return Ok(None);
+    }
+
+    let path = root.join(cgroup.trim_start_matches('/')).join(name);
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            tracing::trace!("cgroup file {name} is unavailable: {err}");
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Parse `key value` lines, skipping any whose value is not a counter.
+fn parse_keyed(contents: &str) -> HashMap<String, u64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_owned(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+impl FromStr for SystemDProperties {
+    type Err = PropertyParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut properties = HashMap::new();
+
+        for line in s.lines() {
+            let (key, value) = line
+                .trim()
+                .split_once('=')
+                .ok_or_else(|| PropertyParseError::MissingDelimeter(line.into()))?;
+
+            properties
+                .entry(key.to_owned())
+                .or_insert_with(Vec::new)
+                .push(value.to_owned());
+        }
+
+        let active = properties
+            .get("ActiveState")
+            .and_then(|values| values.last())
+            .ok_or(PropertyParseError::MissingProperty("ActiveState"))?
+            .parse()?;
+
+        Ok(Self { properties, active })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_enable_state() {
+        assert_eq!(
+            "enabled".parse::<EnableState>().unwrap(),
+            EnableState::Enabled
+        );
+        assert_eq!(
+            "static".parse::<EnableState>().unwrap(),
+            EnableState::Static
+        );
+        assert_eq!(
+            "masked-runtime".parse::<EnableState>().unwrap(),
+            EnableState::MaskedRuntime
+        );
+        assert!("sideways".parse::<EnableState>().is_err());
+    }
+
+    #[test]
+    fn parse_unit_listing() {
+        let listing: UnitListing =
+            "cron.service   loaded active   running Regular background program processing daemon"
+                .parse()
+                .unwrap();
+
+        assert_eq!(listing.unit(), "cron.service");
+        assert_eq!(listing.load(), "loaded");
+        assert!(matches!(listing.active(), ActiveState::Active));
+        assert_eq!(listing.sub(), "running");
+        assert_eq!(
+            listing.description(),
+            "Regular background program processing daemon"
+        );
+
+        assert!("cron.service loaded".parse::<UnitListing>().is_err());
+    }
+
+    #[test]
+    fn systemctl_not_found() {
+        let result = run_with(&mut Command::new("systemctl"), |_| {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(matches!(result, Err(PropertyParseError::SystemctlNotFound)));
+
+        let result = run_with(&mut Command::new("systemctl"), |_| {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(matches!(result, Err(PropertyParseError::CommandError(_))));
+    }
+
+    #[test]
+    fn configure_command() {
+        let result = properties_configured("app.service", |cmd| {
+            cmd.env("PATH", "/nonexistent");
+        });
+        assert!(matches!(result, Err(PropertyParseError::SystemctlNotFound)));
+    }
+
+    #[test]
+    fn stream_records() {
+        let output = "Id=a.service\nActiveState=active\n\nId=b.service\nActiveState=failed\n\n";
+        let mut reader = io::Cursor::new(output);
+
+        let a = next_record(&mut reader).unwrap().unwrap();
+        assert_eq!(a.property("Id"), Some("a.service"));
+        assert!(matches!(a.state(), ActiveState::Active));
+
+        let b = next_record(&mut reader).unwrap().unwrap();
+        assert_eq!(b.property("Id"), Some("b.service"));
+        assert!(matches!(b.state(), ActiveState::Failed));
+
+        assert!(next_record(&mut reader).is_none());
+
+        let mut reader = io::Cursor::new(b"Description=caf\xe9\nActiveState=active\n".as_slice());
+        let unit = next_record(&mut reader).unwrap().unwrap();
+        assert_eq!(unit.property("Description"), Some("caf\u{fffd}"));
+    }
+
+    #[test]
+    fn enable_state_failures() {
+        let unit = OsStr::new("typo.service");
+        let output = Command::new("sh")
+            .args(["-c", "echo 'Access denied' >&2; exit 1"])
+            .output()
+            .unwrap();
+        match enable_state(unit, output) {
+            Err(PropertyParseError::CommandFailed { status, stderr }) => {
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "Access denied");
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+
+        let output = Command::new("sh")
+            .args([
+                "-c",
+                "echo 'Failed to get unit file state for typo.service: No such file or directory' >&2; exit 1",
+            ])
+            .output()
+            .unwrap();
+        assert!(matches!(
+            enable_state(unit, output),
+            Err(PropertyParseError::UnitNotFound(name)) if name == "typo.service"
+        ));
+
+        let output = Command::new("sh")
+            .args(["-c", "echo disabled; exit 1"])
+            .output()
+            .unwrap();
+        assert_eq!(enable_state(unit, output).unwrap(), EnableState::Disabled);
+    }
+
+    #[test]
+    fn stream_exit_status() {
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "printf 'ActiveState=active\\n\\n'; echo 'Access denied' >&2; exit 1",
+        ]);
+        let mut stream = PropertiesStream::spawn(&mut cmd).unwrap();
+
+        assert!(stream.next().unwrap().is_ok());
+        match stream.next() {
+            Some(Err(PropertyParseError::CommandFailed { status, stderr })) => {
+                assert_eq!(status.code(), Some(1));
+                assert_eq!(stderr, "Access denied");
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+        assert!(stream.next().is_none());
+
+        let mut stream = PropertiesStream::spawn(&mut Command::new("true")).unwrap();
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn cgroup_files() {
+        let root = std::env::temp_dir().join(format!("systemd-cgroup-{}", std::process::id()));
+        let unit = root.join("system.slice/app.service");
+        std::fs::create_dir_all(&unit).unwrap();
+        std::fs::write(unit.join("cpu.stat"), "usage_usec 1200\nuser_usec 1000\n").unwrap();
+
+        // Without cgroup.controllers, this isn't a cgroup v2 hierarchy.
+        let cgroup = "/system.slice/app.service";
+        assert!(read_cgroup_file(&root, cgroup, "cpu.stat")
+            .unwrap()
+            .is_none());
+
+        std::fs::write(root.join("cgroup.controllers"), "cpu memory\n").unwrap();
+        let stat = parse_keyed(
+            &read_cgroup_file(&root, cgroup, "cpu.stat")
+                .unwrap()
+                .unwrap(),
+        );
+        assert_eq!(stat.get("usage_usec"), Some(&1200));
+        assert_eq!(stat.get("user_usec"), Some(&1000));
+        assert!(read_cgroup_file(&root, cgroup, "memory.current")
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(read_cgroup_file(&root, cgroup, "cpu.stat")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cgroup_missing_paths() {
+        let root =
+            std::env::temp_dir().join(format!("systemd-cgroup-missing-{}", std::process::id()));
+        let cgroup = "/system.slice/gone.service";
+
+        // The hierarchy itself is missing, so there is no cgroup.controllers.
+        assert!(read_cgroup_file(&root, cgroup, "cpu.stat")
+            .unwrap()
+            .is_none());
+
+        // The hierarchy exists, but the unit's cgroup has been removed.
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("cgroup.controllers"), "cpu memory\n").unwrap();
+        assert!(read_cgroup_file(&root, cgroup, "cpu.stat")
+            .unwrap()
+            .is_none());
+
+        // Other errors, such as reading a directory, are reported.
+        std::fs::create_dir_all(root.join("system.slice/gone.service/cpu.stat")).unwrap();
+        assert!(read_cgroup_file(&root, cgroup, "cpu.stat").is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn parse_slice() {
+        let properties: SystemDProperties = "ActiveState=active
+Slice=system-app.slice
+ControlGroup=/system.slice/system-app.slice/app.service"
+            .parse()
+            .unwrap();
+        assert_eq!(properties.slice(), Some("system-app.slice"));
+        assert_eq!(
+            properties.control_group(),
+            Some("/system.slice/system-app.slice/app.service")
+        );
+
+        let properties: SystemDProperties = "ActiveState=inactive\nSlice=".parse().unwrap();
+        assert_eq!(properties.slice(), None);
+    }
+
+    #[test]
+    fn parse_tasks() {
+        let properties: SystemDProperties = "ActiveState=active\nTasksCurrent=12\nTasksMax=4915"
+            .parse()
+            .unwrap();
+        assert_eq!(properties.tasks_current(), Some(12));
+        assert_eq!(properties.tasks_max(), Some(4915));
+
+        let properties: SystemDProperties =
+            "ActiveState=inactive\nTasksCurrent=18446744073709551615\nTasksMax=infinity"
+                .parse()
+                .unwrap();
+        assert_eq!(properties.tasks_current(), None);
+        assert_eq!(properties.tasks_max(), None);
+    }
+
+    #[test]
+    fn retry_bus_errors() {
+        let bus_error = || {
+            let mut cmd = Command::new("sh");
+            cmd.args([
+                "-c",
+                "echo 'Failed to connect to bus: No such file' >&2; exit 1",
+            ]);
+            succeeded(run(&mut cmd)?)
+        };
+        assert!(bus_error().unwrap_err().is_bus_error());
+
+        let mut calls = 0;
+        let result = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            if calls < 3 {
+                bus_error()?;
+            }
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result = retry(2, Duration::from_millis(1), || {
+            calls += 1;
+            bus_error()
+        });
+        assert!(result.unwrap_err().is_bus_error());
+        assert_eq!(calls, 2);
+
+        let mut calls = 0;
+        let result: Result<(), _> = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err(PropertyParseError::MissingProperty("ActiveState"))
+        });
+        assert!(matches!(
+            result,
+            Err(PropertyParseError::MissingProperty(_))
+        ));
+        assert_eq!(calls, 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn state_changes() {
+        fn assert_stream<S: futures_core::Stream + Send + Unpin>(_: &S) {}
+
+        let mut watch = watch_state("app.service", Duration::from_secs(1));
+        assert_stream(&watch);
+        assert_eq!(
+            watch.changed(ActiveState::Activating),
+            Some(ActiveState::Activating)
+        );
+        assert_eq!(watch.changed(ActiveState::Activating), None);
+        assert_eq!(
+            watch.changed(ActiveState::Active),
+            Some(ActiveState::Active)
+        );
+    }
+
+    #[test]
+    fn poll_main_pid() {
+        let mut polls = 0;
+        let pid = poll_until(Duration::from_secs(5), || {
+            polls += 1;
+            let properties: SystemDProperties =
+                format!("ActiveState=activating\nMainPID={}", (polls - 1) * 42).parse()?;
+            Ok(properties.main_pid())
+        });
+        assert_eq!(pid.unwrap(), 42);
+        assert_eq!(polls, 2);
+
+        let result: Result<u32, _> = poll_until(Duration::from_millis(20), || Ok(None));
+        assert!(matches!(result, Err(PropertyParseError::Timeout(_))));
+    }
+
+    #[test]
+    fn parse_units_with_warnings() {
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "printf 'Id=a.service\\nActiveState=active\\n\\nId=b.service\\nActiveState=failed\\n'; \
+             echo 'Invalid unit name \"bad name\" escaped as \"bad\\x20name\".' >&2",
+        ]);
+        let parsed = parse_units(run(&mut cmd).unwrap()).unwrap();
+
+        let ids: Vec<_> = parsed
+            .units()
+            .iter()
+            .map(|unit| unit.property("Id").unwrap())
+            .collect();
+        assert_eq!(ids, ["a.service", "b.service"]);
+        assert_eq!(parsed.warnings().len(), 1);
+        assert!(parsed.warnings()[0].starts_with("Invalid unit name"));
+        assert_eq!(parsed.into_units().len(), 2);
+    }
+
+    #[test]
+    fn parse_failure_snippet() {
+        match parse_properties("ActiveState=active\nnot a property") {
+            Err(PropertyParseError::Parse { snippet, source }) => {
+                assert_eq!(snippet, "ActiveState=active\nnot a property");
+                assert!(
+                    matches!(*source, PropertyParseError::MissingDelimeter(line) if line == "not a property")
+                );
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
+        assert_eq!(snippet("héllo", 2), "h");
+        assert_eq!(snippet("héllo", 3), "hé");
+        assert_eq!(snippet("hi", 512), "hi");
+    }
+
+    #[test]
+    fn command_timeout() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("5");
+        let result = run_timeout(&mut cmd, Duration::from_millis(50));
+        assert!(matches!(result, Err(PropertyParseError::Timeout(_))));
+
+        let mut cmd = Command::new("echo");
+        cmd.arg("ActiveState=active");
+        let output = run_timeout(&mut cmd, Duration::from_secs(5)).unwrap();
+        assert_eq!(output.stdout, b"ActiveState=active\n");
+    }
+
+    #[test]
+    fn parse_n_restarts() {
+        let properties: SystemDProperties = "ActiveState=active\nNRestarts=5".parse().unwrap();
+        assert_eq!(properties.n_restarts(), Some(5));
+
+        let properties: SystemDProperties = "ActiveState=active".parse().unwrap();
+        assert_eq!(properties.n_restarts(), None);
+    }
+
+    #[test]
+    fn unit_status() {
+        let properties: SystemDProperties =
+            "ActiveState=active\nLoadState=loaded\nSubState=running\nDescription=My App"
+                .parse()
+                .unwrap();
+
+        let status = properties.status();
+        assert!(matches!(status.active(), ActiveState::Active));
+        assert_eq!(status.load(), "loaded");
+        assert_eq!(status.sub(), "running");
+        assert_eq!(status.description(), "My App");
+    }
+
+    #[test]
+    fn parse_conditions() {
+        let properties: SystemDProperties =
+            "ActiveState=inactive\nConditionResult=no\nAssertResult=yes"
+                .parse()
+                .unwrap();
+        assert_eq!(properties.condition_result(), Some(false));
+        assert_eq!(properties.assert_result(), Some(true));
+
+        let properties: SystemDProperties = "ActiveState=inactive".parse().unwrap();
+        assert_eq!(properties.condition_result(), None);
+    }
+
+    #[test]
+    fn parse_accounting() {
+        let properties: SystemDProperties =
+            "ActiveState=active\nMemoryAccounting=yes\nCPUAccounting=no\nMemoryCurrent=[not set]"
+                .parse()
+                .unwrap();
+        assert_eq!(properties.memory_accounting(), Some(true));
+        assert_eq!(properties.cpu_accounting(), Some(false));
+        assert_eq!(properties.tasks_accounting(), None);
+    }
+
+    #[test]
+    fn parse_environment() {
+        let properties: SystemDProperties = r#"ActiveState=active
+Environment=LANG=C "GREETING=hello world" URL=http://x/?a=b EMPTY= 'Q=it\'s' TAB=a\tb CAFE=caf\xc3\xa9 bogus"#
+            .parse()
+            .unwrap();
+
+        let environment = properties.environment();
+        let pairs: Vec<_> = environment
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("LANG", "C"),
+                ("GREETING", "hello world"),
+                ("URL", "http://x/?a=b"),
+                ("EMPTY", ""),
+                ("Q", "it's"),
+                ("TAB", "a\tb"),
+                ("CAFE", "café"),
+            ]
+        );
+
+        let properties: SystemDProperties = "ActiveState=active\nEnvironment=".parse().unwrap();
+        assert!(properties.environment().is_empty());
+    }
+
+    #[test]
+    fn parse_result() {
+        let properties: SystemDProperties = "ActiveState=failed\nResult=oom-kill".parse().unwrap();
+        assert_eq!(properties.result(), Some(UnitResult::OomKill));
+        assert_eq!(properties.result().unwrap().to_string(), "oom-kill");
+
+        assert_eq!(
+            UnitResult::from("brand-new"),
+            UnitResult::Other("brand-new".into())
+        );
+
+        let properties: SystemDProperties = "ActiveState=failed".parse().unwrap();
+        assert_eq!(properties.result(), None);
+    }
+
+    #[test]
+    fn parse_service_type() {
+        let properties: SystemDProperties =
+            "ActiveState=active\nType=notify-reload".parse().unwrap();
+        assert_eq!(properties.service_type(), Some(ServiceType::NotifyReload));
+        assert!(properties.service_type().unwrap().expects_ready());
+        assert!(!ServiceType::Simple.expects_ready());
+
+        let properties: SystemDProperties = "ActiveState=active".parse().unwrap();
+        assert_eq!(properties.service_type(), None);
+    }
+
+    #[test]
+    fn timestamps_ago() {
+        let now = crate::monotonic_now().unwrap();
+        let started = (now - Duration::from_secs(180)).as_micros();
+        let properties: SystemDProperties = format!(
+            "ActiveState=active\nActiveEnterTimestampMonotonic={started}\nInactiveEnterTimestampMonotonic=0"
+        )
+        .parse()
+        .unwrap();
+
+        let ago = properties.active_enter_ago().unwrap();
+        assert!(ago >= Duration::from_secs(180) && ago < Duration::from_secs(190));
+        assert_eq!(properties.inactive_enter_ago(), None);
+    }
+
+    #[test]
+    fn condition_timestamp() {
+        let properties: SystemDProperties =
+            "ActiveState=inactive\nConditionResult=no\nConditionTimestamp=@1700000000"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            properties.condition_timestamp(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+
+        let properties: SystemDProperties =
+            "ActiveState=inactive\nConditionTimestamp=Tue 2023-11-14 22:13:20 UTC"
+                .parse()
+                .unwrap();
+        assert_eq!(properties.condition_timestamp(), None);
+
+        let properties: SystemDProperties =
+            "ActiveState=inactive\nConditionTimestamp=".parse().unwrap();
+        assert_eq!(properties.condition_timestamp(), None);
+    }
+
+    #[test]
+    fn format_timespans() {
+        assert_eq!(format_timespan(Duration::from_secs(182)), "3min 2s");
+        assert_eq!(format_timespan(Duration::from_secs(90_000)), "1d 1h");
+        assert_eq!(format_timespan(Duration::from_millis(1_500)), "1s");
+        assert_eq!(format_timespan(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_timespan(Duration::ZERO), "0");
+    }
+
+    #[test]
+    fn parse_durations() {
+        let cases = [
+            ("250us", Some(Duration::from_micros(250))),
+            ("500ms", Some(Duration::from_millis(500))),
+            ("90", Some(Duration::from_secs(90))),
+            ("20s", Some(Duration::from_secs(20))),
+            ("2.5s", Some(Duration::from_millis(2_500))),
+            ("3min", Some(Duration::from_secs(180))),
+            ("1h", Some(Duration::from_secs(3_600))),
+            ("2d", Some(Duration::from_secs(172_800))),
+            ("1w", Some(Duration::from_secs(604_800))),
+            ("3min 20s", Some(Duration::from_secs(200))),
+            ("1h30min", Some(Duration::from_secs(5_400))),
+            ("1d 1h", Some(Duration::from_secs(90_000))),
+            ("5 min", Some(Duration::from_secs(300))),
+            ("infinity", None),
+            ("", None),
+            ("3 fortnights", None),
+            ("min", None),
+            ("99999999999999999999999.5y", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_systemd_duration(value), expected, "{value:?}");
+        }
+
+        let duration = Duration::from_secs(93_784);
+        assert_eq!(
+            parse_systemd_duration(&format_timespan(duration)),
+            Some(duration)
+        );
+
+        let properties: SystemDProperties =
+            "ActiveState=active\nTimeoutStartUSec=1min 30s\nTimeoutStopUSec=infinity"
+                .parse()
+                .unwrap();
+        assert_eq!(properties.timeout_start(), Some(Duration::from_secs(90)));
+        assert_eq!(properties.timeout_stop(), None);
+    }
+
+    #[test]
+    fn parse_notify_access() {
+        let properties: SystemDProperties =
+            "ActiveState=active\nNotifyAccess=main".parse().unwrap();
+        assert_eq!(properties.notify_access(), Some(NotifyAccess::Main));
+        assert!(!NotifyAccess::Main.accepts_any_process());
+        assert!(NotifyAccess::All.accepts_any_process());
+        assert_eq!("none".parse::<NotifyAccess>().unwrap(), NotifyAccess::None);
+
+        let properties: SystemDProperties = "ActiveState=active".parse().unwrap();
+        assert_eq!(properties.notify_access(), None);
+    }
+
+    #[test]
+    fn parse_oom() {
+        let properties: SystemDProperties =
+            "ActiveState=active\nOOMPolicy=stop\nOOMScoreAdjust=-500"
+                .parse()
+                .unwrap();
+        assert_eq!(properties.oom_policy(), Some(OomPolicy::Stop));
+        assert_eq!(properties.oom_score_adjust(), Some(-500));
+
+        let properties: SystemDProperties = "ActiveState=active\nOOMPolicy=freeze".parse().unwrap();
+        let policy = properties.oom_policy().unwrap();
+        assert_eq!(policy, OomPolicy::Other("freeze".into()));
+        assert_eq!(policy.to_string(), "freeze");
+        assert_eq!(properties.oom_score_adjust(), None);
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        let properties: SystemDProperties =
+            "ActiveState=active\nAfter=network.target  basic.target\nWants="
+                .parse()
+                .unwrap();
+        assert_eq!(
+            properties.dependencies(DependencyKind::After),
+            vec!["network.target", "basic.target"]
+        );
+        assert!(properties.dependencies(DependencyKind::Wants).is_empty());
+        assert!(properties.dependencies(DependencyKind::PartOf).is_empty());
+
+        let properties: SystemDProperties =
+            "ActiveState=active\nTriggeredBy=app.socket app-admin.socket"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            properties.triggered_by(),
+            vec!["app.socket", "app-admin.socket"]
+        );
+        assert!(properties.triggers().is_empty());
+    }
+
+    #[test]
+    fn parse_exec_start() {
+        let properties: SystemDProperties = "ActiveState=active
+ExecStart={ path=/usr/bin/app ; argv[]=app --config \"/etc/my app.toml\" ; ignore_errors=no ; start_time=[n/a] ; stop_time=[n/a] ; pid=0 ; code=(null) ; status=0/0 }
+ExecStart={ path=/usr/bin/sidecar ; argv[]=sidecar ; ignore_errors=yes ; start_time=[n/a] ; stop_time=[n/a] ; pid=0 ; code=(null) ; status=0/0 }"
+            .parse()
+            .unwrap();
+
+        let commands = properties.exec_start();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].path(), Path::new("/usr/bin/app"));
+        assert_eq!(commands[0].argv(), ["app", "--config", "/etc/my app.toml"]);
+        assert!(commands[0].flags().is_empty());
+        assert_eq!(commands[1].path(), Path::new("/usr/bin/sidecar"));
+        assert_eq!(commands[1].argv(), ["sidecar"]);
+        assert_eq!(commands[1].flags(), ["ignore-failure"]);
+
+        let commands = parse_exec_commands(
+            "{ path=/bin/true ; argv[]=true ; flags=ignore-failure privileged ; start_time=[n/a] }",
+        );
+        assert_eq!(commands[0].flags(), ["ignore-failure", "privileged"]);
+
+        let properties: SystemDProperties = "ActiveState=active".parse().unwrap();
+        assert!(properties.exec_start().is_empty());
+    }
+
+    #[test]
+    fn parse_unit_paths() {
+        let properties: SystemDProperties = "ActiveState=active
+FragmentPath=/etc/systemd/system/app.service
+DropInPaths=/etc/systemd/system/app.service.d/a.conf /run/systemd/system/app.service.d/b.conf"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            properties.fragment_path(),
+            Some(Utf8PathBuf::from("/etc/systemd/system/app.service"))
+        );
+        assert_eq!(
+            properties.drop_in_paths(),
+            vec![
+                Utf8PathBuf::from("/etc/systemd/system/app.service.d/a.conf"),
+                Utf8PathBuf::from("/run/systemd/system/app.service.d/b.conf"),
+            ]
+        );
+
+        let properties: SystemDProperties = "ActiveState=active\nFragmentPath=\nDropInPaths="
+            .parse()
+            .unwrap();
+        assert_eq!(properties.fragment_path(), None);
+        assert!(properties.drop_in_paths().is_empty());
+    }
+
+    #[test]
+    fn read_drop_ins() {
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("systemd-drop-ins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.conf");
+        let b = dir.join("b.conf");
+        std::fs::write(&a, "[Service]\nNice=5\n").unwrap();
+        std::fs::write(&b, "[Service]\nUser=app\n").unwrap();
+
+        let properties: SystemDProperties = format!(
+            "ActiveState=active\nDropInPaths={a} {} {b}",
+            dir.join("missing.conf"),
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            properties.effective_drop_ins().unwrap(),
+            vec![
+                (a, "[Service]\nNice=5\n".to_owned()),
+                (b, "[Service]\nUser=app\n".to_owned()),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,364 @@
+//! A high-level service lifecycle, built from the notify primitives
+
+use std::collections::hash_map::RandomState;
+use std::ffi::OsString;
+use std::hash::{BuildHasher, Hasher};
+use std::net::TcpListener;
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use crate::journal::{self, Priority, JOURNAL_SOCKET};
+use crate::notify::{
+    watchdog_interval, Message, Notification, NotifyError, SystemDNotify, NOTIFY_SOCKET,
+};
+use crate::socket::{sockets, SocketError, SystemDSocket};
+
+/// The default watchdog jitter, so pings land between 45% and 55% of the interval
+pub const DEFAULT_WATCHDOG_JITTER: f64 = 0.1;
+
+/// Errors that can occur while setting up an activated service
+#[derive(Debug, Error)]
+pub enum StartupError {
+    /// The activated listener could not be claimed
+    #[error("Activated listener: {0}")]
+    Socket(#[from] SocketError),
+
+    /// The notifier could not be constructed
+    #[error("Notifier: {0}")]
+    Notify(#[from] NotifyError),
+
+    /// Systemd did not pass any sockets
+    #[error("No sockets were passed by systemd")]
+    NoSockets,
+}
+
+/// Claim the first socket passed by systemd as a listener, along with a notifier
+///
+/// This is the usual startup sequence for a socket activated web service: the
+/// listener is non-blocking, ready for e.g. `tokio::net::TcpListener::from_std`,
+/// and the notifier is `None` when `$NOTIFY_SOCKET` is unset. Any further
+/// sockets which systemd passed are closed, even if the first can't be claimed.
+///
+/// This is `async` because the notifier registers its socket with the tokio
+/// runtime, so it must be awaited from within one.
+pub async fn activated_listener_and_notify(
+) -> Result<(TcpListener, Option<SystemDNotify>), StartupError> {
+    activated_from(sockets()?, std::env::var_os(NOTIFY_SOCKET))
+}
+
+fn activated_from(
+    sockets: Vec<SystemDSocket>,
+    notify_socket: Option<OsString>,
+) -> Result<(TcpListener, Option<SystemDNotify>), StartupError> {
+    let mut sockets = sockets.into_iter();
+    let socket = sockets.next().ok_or(StartupError::NoSockets)?;
+
+    // SystemDSocket doesn't close its descriptor, so take ownership of the rest to close them.
+    for socket in sockets {
+        drop(OwnedFd::from(socket));
+    }
+
+    let listener = socket.listener()?;
+
+    let notify = match notify_socket {
+        Some(path) if !path.is_empty() => Some(SystemDNotify::connect(path)?),
+        _ => None,
+    };
+
+    Ok((listener, notify))
+}
+
+/// A running service, which keeps systemd informed of its lifecycle
+///
+/// Starting a service sends `READY=1` and, if systemd has enabled the watchdog,
+/// pings it in the background. Stopping the service, either with [Service::stop]
+/// or by dropping it, sends `STOPPING=1`.
+#[derive(Debug)]
+pub struct Service {
+    notify: SystemDNotify,
+    watchdog: Option<JoinHandle<()>>,
+    journal: Option<PathBuf>,
+    stopped: bool,
+}
+
+impl Service {
+    /// Start the service, using the notification socket and watchdog from the environment
+    pub async fn start() -> Result<Self, NotifyError> {
+        Self::start_with(SystemDNotify::from_environment()?, watchdog_interval()).await
+    }
+
+    /// Start the service with an explicit notifier and watchdog interval
+    ///
+    /// The watchdog is pinged at around half of the given interval, as recommended
+    /// by `sd_watchdog_enabled(3)`, with [DEFAULT_WATCHDOG_JITTER]. This must be
+    /// called from within a tokio runtime.
+    pub async fn start_with(
+        notify: SystemDNotify,
+        watchdog: Option<Duration>,
+    ) -> Result<Self, NotifyError> {
+        Self::start_with_jitter(notify, watchdog, DEFAULT_WATCHDOG_JITTER).await
+    }
+
+    /// Start the service, randomizing each watchdog ping within a window
+    ///
+    /// `jitter` is the width of the window, as a fraction of the watchdog interval,
+    /// centered on half of the interval. For example, `0.2` pings between 40% and 60%
+    /// of the interval. This avoids synchronized bursts of pings from many instances
+    /// of a service which were started together.
+    pub async fn start_with_jitter(
+        notify: SystemDNotify,
+        watchdog: Option<Duration>,
+        jitter: f64,
+    ) -> Result<Self, NotifyError> {
+        notify.send(Notification::Ready).await?;
+
+        let watchdog =
+            watchdog.map(|interval| spawn_watchdog(notify.clone(), interval, jitter, || true));
+
+        Ok(Self {
+            notify,
+            watchdog,
+            journal: None,
+            stopped: false,
+        })
+    }
+
+    /// Also send each `STATUS=` sent with [Service::send] to the journal, at INFO priority
+    ///
+    /// This keeps the journal in sync with the status line shown by `systemctl status`,
+    /// using [journal::send]. Failing to write to the journal is logged, rather than
+    /// failing the notification.
+    pub fn with_status_mirroring(mut self) -> Self {
+        self.journal = Some(PathBuf::from(JOURNAL_SOCKET));
+        self
+    }
+
+    /// The notifier for this service, to send status updates
+    pub fn notify(&self) -> &SystemDNotify {
+        &self.notify
+    }
+
+    /// Send a message to systemd, mirroring any status if enabled
+    ///
+    /// See [Service::with_status_mirroring].
+    pub async fn send<M: Into<Message>>(&self, message: M) -> Result<(), NotifyError> {
+        let message = message.into();
+        if let Some(journal) = &self.journal {
+            for notification in message.iter() {
+                if let Notification::Status(status) = notification {
+                    if let Err(err) = journal::send_to(journal, Priority::Info, status) {
+                        tracing::warn!("Failed to mirror status to the journal: {err}");
+                    }
+                }
+            }
+        }
+        self.notify.send(message).await
+    }
+
+    /// Stop the service, disarming the watchdog and notifying systemd
+    pub async fn stop(mut self) -> Result<(), NotifyError> {
+        self.disarm();
+        self.stopped = true;
+        self.notify.send(Notification::Stopping).await
+    }
+
+    fn disarm(&mut self) {
+        if let Some(watchdog) = self.watchdog.take() {
+            watchdog.abort();
+        }
+    }
+}
+
+/// Ping the watchdog around every half `interval`, while `check` passes.
+pub(crate) fn spawn_watchdog<F>(
+    notify: SystemDNotify,
+    interval: Duration,
+    jitter: f64,
+    check: F,
+) -> JoinHandle<()>
+where
+    F: Fn() -> bool + Send + 'static,
+{
+    let jitter = jitter.clamp(0.0, 1.0);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(ping_delay(interval, jitter, random())).await;
+            if !check() {
+                tracing::warn!("Health check failed, not pinging systemd watchdog");
+                continue;
+            }
+            if let Err(err) = notify.ping_watchdog().await {
+                tracing::warn!("Failed to ping systemd watchdog: {err}");
+            }
+        }
+    })
+}
+
+/// The delay before the next watchdog ping, given a uniform random sample in `[0, 1)`.
+fn ping_delay(interval: Duration, jitter: f64, sample: f64) -> Duration {
+    interval.mul_f64(0.5 + jitter * (sample - 0.5))
+}
+
+/// A random sample in `[0, 1)`, using the standard library's randomly keyed hasher.
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.disarm();
+        if !self.stopped {
+            if let Err(err) = self.notify.try_send(Notification::Stopping) {
+                tracing::warn!("Failed to notify systemd of stopping: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::net::UnixDatagram;
+
+    fn bind(name: &str) -> (UnixDatagram, PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("systemd-service-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (UnixDatagram::bind(&path).unwrap(), path)
+    }
+
+    async fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = vec![0; 4096];
+        let n = socket.recv(&mut buf).await.unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn watchdog_jitter() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(ping_delay(interval, 0.0, 0.9), Duration::from_secs(5));
+        assert_eq!(ping_delay(interval, 0.2, 0.0), Duration::from_secs(4));
+        assert_eq!(ping_delay(interval, 0.2, 0.5), Duration::from_secs(5));
+        assert!(ping_delay(interval, 0.2, 0.999) < Duration::from_secs(6));
+
+        let sample = random();
+        assert!((0.0..1.0).contains(&sample));
+    }
+
+    #[test]
+    fn activated_with_notify_socket() {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let (systemd, path) = bind("activated");
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                let address = listener.local_addr().unwrap();
+                let socket = SystemDSocket::from_fd(None, listener.into());
+
+                let (listener, notify) =
+                    activated_from(vec![socket], Some(path.clone().into())).unwrap();
+                assert_eq!(listener.local_addr().unwrap(), address);
+
+                notify.unwrap().send(Notification::Ready).await.unwrap();
+                assert_eq!(recv(&systemd).await, "READY=1\n");
+
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                let socket = SystemDSocket::from_fd(None, listener.into());
+                let (_, notify) = activated_from(vec![socket], Some(OsString::new())).unwrap();
+                assert!(notify.is_none());
+
+                assert!(matches!(
+                    activated_from(Vec::new(), None),
+                    Err(StartupError::NoSockets)
+                ));
+
+                std::fs::remove_file(&path).unwrap();
+            })
+    }
+
+    #[test]
+    fn health_checked_watchdog() {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let (systemd, path) = bind("health");
+                let notify = SystemDNotify::connect(path.clone()).unwrap();
+
+                let healthy = Arc::new(AtomicBool::new(false));
+                let check = {
+                    let healthy = healthy.clone();
+                    move || healthy.load(Ordering::SeqCst)
+                };
+                let watchdog = spawn_watchdog(notify, Duration::from_millis(20), 0.0, check);
+
+                let mut buf = [0; 64];
+                let unhealthy =
+                    tokio::time::timeout(Duration::from_millis(50), systemd.recv(&mut buf)).await;
+                assert!(unhealthy.is_err(), "Pinged while unhealthy");
+
+                healthy.store(true, Ordering::SeqCst);
+                assert_eq!(recv(&systemd).await, "WATCHDOG=1\n");
+
+                watchdog.abort();
+                std::fs::remove_file(&path).unwrap();
+            })
+    }
+
+    #[test]
+    fn lifecycle() {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let (systemd, path) = bind("lifecycle");
+                let notify = SystemDNotify::connect(path.clone()).unwrap();
+
+                let service = Service::start_with(notify, Some(Duration::from_millis(20)))
+                    .await
+                    .unwrap();
+                assert_eq!(recv(&systemd).await, "READY=1\n");
+                assert_eq!(recv(&systemd).await, "WATCHDOG=1\n");
+
+                service.stop().await.unwrap();
+                let mut message = recv(&systemd).await;
+                while message == "WATCHDOG=1\n" {
+                    message = recv(&systemd).await;
+                }
+                assert_eq!(message, "STOPPING=1\n");
+
+                let (journal, journal_path) = bind("lifecycle-journal");
+                let notify = SystemDNotify::connect(path.clone()).unwrap();
+                let mut service = Service::start_with(notify, None)
+                    .await
+                    .unwrap()
+                    .with_status_mirroring();
+                assert_eq!(service.journal.as_deref(), Some(JOURNAL_SOCKET.as_ref()));
+                service.journal = Some(journal_path.clone());
+                assert_eq!(recv(&systemd).await, "READY=1\n");
+                service
+                    .send(Notification::Status("Serving".into()))
+                    .await
+                    .unwrap();
+                assert_eq!(recv(&systemd).await, "STATUS=Serving\n");
+                assert_eq!(recv(&journal).await, "PRIORITY=6\nMESSAGE=Serving\n");
+                drop(service);
+                assert_eq!(recv(&systemd).await, "STOPPING=1\n");
+
+                std::fs::remove_file(&path).unwrap();
+                std::fs::remove_file(&journal_path).unwrap();
+            })
+    }
+}
@@ -1,10 +1,17 @@
 //! Access sockets passed from systemd
 
-use std::fs::File;
-use std::io;
-use std::net::TcpListener;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{
+    Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener, TcpStream,
+};
+use std::os::unix::net::{UnixDatagram, UnixStream};
 use std::os::unix::prelude::*;
+use std::path::PathBuf;
 use std::process;
+use std::sync::{Mutex, OnceLock};
 
 use thiserror::Error;
 
@@ -12,6 +19,10 @@ const SD_FD_OFFSET: i32 = 3;
 const LISTEN_FDS: &str = "LISTEN_FDS";
 const LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
 const LISTEN_PID: &str = "LISTEN_PID";
+const LISTEN_FDS_FIRST_FD: &str = "LISTEN_FDS_FIRST_FD";
+
+/// The name systemd uses for descriptors without a `FileDescriptorName=`
+const UNKNOWN_NAME: &str = "unknown";
 
 /// Errors that can occur when trying to access systemd-owned sockets
 #[derive(Debug, Error)]
@@ -28,6 +39,14 @@ pub enum SocketError {
     #[error("file descriptor {} is not a socket", .0)]
     NotSocket(RawFd),
 
+    /// The file descriptor that systemd gave us is not the expected socket type
+    #[error("file descriptor {0} is not a {1} socket")]
+    WrongType(RawFd, &'static str),
+
+    /// The socket that systemd gave us is not listening, e.g. it is a connection from `Accept=yes`
+    #[error("file descriptor {0} is not a listening socket")]
+    NotListening(RawFd),
+
     /// Missing a systemd variable
     #[error("Missing ${0} variable")]
     MissingVar(&'static str),
@@ -35,6 +54,10 @@ pub enum SocketError {
     /// Invalid value for a systemd variable
     #[error("Invalid ${0}={1}")]
     InvalidVar(&'static str, String),
+
+    /// Systemd did not pass a socket with this name
+    #[error("No socket named {0}")]
+    MissingName(String),
 }
 
 pub(crate) fn var(name: &'static str) -> Result<String, SocketError> {
@@ -50,18 +73,104 @@ pub fn sockets() -> Result<Vec<SystemDSocket>, SocketError> {
     let listen_pid = var(LISTEN_PID);
     let listen_fds = var(LISTEN_FDS);
     let listen_fd_names = var(LISTEN_FDNAMES).ok();
+    let listen_fds_first_fd = var(LISTEN_FDS_FIRST_FD).ok();
 
     construct_sockets(
         listen_fds?.as_str(),
         listen_fd_names.as_deref(),
         listen_pid?.as_str(),
+        listen_fds_first_fd.as_deref(),
     )
 }
 
+/// Get the descriptors that systemd has passed to us, with their names
+///
+/// This is the lowest level access, after the `$LISTEN_PID` check: the caller
+/// decides what each descriptor is, and how to use it.
+pub fn listen_owned_fds() -> Result<Vec<(Option<String>, OwnedFd)>, SocketError> {
+    Ok(sockets()?
+        .into_iter()
+        .map(SystemDSocket::into_parts)
+        .collect())
+}
+
+/// Get the sockets that systemd has passed to us, computing them only once
+///
+/// Systemd's descriptors may only be claimed once, so subsystems which all want
+/// access to them should share this set rather than calling [sockets] themselves.
+/// Each subsystem claims its own sockets by name, e.g. with
+/// [SystemDSockets::take_listener], and a socket which has been claimed is gone
+/// from the set. The environment is left untouched, so a direct call to [sockets]
+/// would still hand out a second set of owners for the same descriptors.
+pub fn sockets_once() -> Result<&'static Mutex<SystemDSockets>, &'static SocketError> {
+    static SOCKETS: OnceLock<Result<Mutex<SystemDSockets>, SocketError>> = OnceLock::new();
+
+    SOCKETS
+        .get_or_init(|| sockets_map().map(Mutex::new))
+        .as_ref()
+}
+
+/// Get the sockets that systemd has passed to us, keyed by name
+///
+/// See [SystemDSockets] for how unnamed sockets are handled.
+pub fn sockets_map() -> Result<SystemDSockets, SocketError> {
+    sockets().map(SystemDSockets::from_iter)
+}
+
+/// Get the sockets that systemd has passed to us as named TCP listeners
+///
+/// Every socket must be a TCP socket, otherwise this fails on the first which
+/// is not. Use [sockets_tcp_filtered] for units which also pass other sockets.
+///
+/// Every socket is checked before any is converted, so a failed check leaves
+/// all of the descriptors open, and unclaimed.
+pub fn sockets_tcp() -> Result<Vec<(Option<String>, TcpListener)>, SocketError> {
+    tcp_listeners(sockets()?, true)
+}
+
+/// Get the TCP sockets that systemd has passed to us as named listeners
+///
+/// Sockets of other types, and descriptors which are not sockets at all (e.g.
+/// from `ListenFIFO=`), are skipped, and left open.
+pub fn sockets_tcp_filtered() -> Result<Vec<(Option<String>, TcpListener)>, SocketError> {
+    tcp_listeners(sockets()?, false)
+}
+
+fn tcp_listeners(
+    sockets: Vec<SystemDSocket>,
+    strict: bool,
+) -> Result<Vec<(Option<String>, TcpListener)>, SocketError> {
+    // Check every socket before claiming any, so that an error can't leave
+    // some descriptors closed and others open.
+    let mut tcp = Vec::with_capacity(sockets.len());
+    for socket in sockets {
+        let is_tcp = match is_tcp(socket.as_fd()) {
+            Ok(is_tcp) => is_tcp,
+            Err(SocketError::NotSocket(_)) if !strict => false,
+            Err(err) => return Err(err),
+        };
+        if !is_tcp {
+            if strict {
+                return Err(SocketError::WrongType(socket.fd, "TCP"));
+            }
+            continue;
+        }
+        if !is_listening(socket.as_fd())? {
+            return Err(SocketError::NotListening(socket.fd));
+        }
+        tcp.push(socket);
+    }
+
+    tcp.into_iter()
+        .map(|socket| Ok((socket.name.clone(), socket.listener()?)))
+        .collect()
+}
+
 fn construct_sockets(
     listen_fds: &str,
     listen_fd_names: Option<&str>,
     listen_pid: &str,
+    listen_fds_first_fd: Option<&str>,
 ) -> Result<Vec<SystemDSocket>, SocketError> {
     let pid = listen_pid
         .parse::<u32>()
@@ -75,31 +184,150 @@ fn construct_sockets(
         .parse::<usize>()
         .map_err(|_| SocketError::InvalidVar(LISTEN_FDS, listen_fds.into()))?;
 
+    let first_fd = first_fd(listen_fds_first_fd)?;
+
+    let names: Vec<_> = listen_fd_names
+        .map(|names| names.split(':').collect())
+        .unwrap_or_default();
+
     if let Some(names_value) = listen_fd_names {
-        let names: Vec<_> = names_value.split(':').collect();
-
-        if names.len() == n {
-            return Ok((SD_FD_OFFSET..)
-                .take(n)
-                .zip(names)
-                .map(|(fd, name)| SystemDSocket::new(name, fd))
-                .collect());
-        } else if !names.is_empty() {
+        if names.len() != n {
             tracing::warn!("Invalid ${}={}", LISTEN_FDNAMES, names_value);
-        };
-    };
+        }
+    }
 
-    Ok((SD_FD_OFFSET..)
+    // Name as many sockets as we have names for, and leave the rest unnamed.
+    let mut names = names.into_iter();
+    Ok((first_fd..)
         .take(n)
-        .map(SystemDSocket::unnamed)
+        .map(|fd| match names.next() {
+            Some(name) if !name.is_empty() && name != UNKNOWN_NAME => SystemDSocket::new(name, fd),
+            _ => SystemDSocket::unnamed(fd),
+        })
         .collect())
 }
 
+/// Systemd may start the descriptors somewhere other than the usual 3.
+fn first_fd(listen_fds_first_fd: Option<&str>) -> Result<RawFd, SocketError> {
+    match listen_fds_first_fd {
+        Some(first) => first
+            .parse::<RawFd>()
+            .ok()
+            .filter(|fd| *fd >= 0)
+            .ok_or_else(|| SocketError::InvalidVar(LISTEN_FDS_FIRST_FD, first.into())),
+        None => Ok(SD_FD_OFFSET),
+    }
+}
+
+/// Get the number of sockets that systemd has passed to us, without claiming them
+///
+/// Like [sockets], this checks that they were passed to this process, but it
+/// leaves the descriptors alone, so it can be used to fail fast on a misconfigured
+/// unit before wiring anything up.
+pub fn listen_fds_count() -> Result<usize, SocketError> {
+    sockets_diagnostic()?.checked_fds()
+}
+
+/// Describe the sockets systemd says it passed, for troubleshooting socket activation
+///
+/// This parses the `$LISTEN_*` variables without checking `$LISTEN_PID` against
+/// our PID, so it also works when inspecting an environment meant for another
+/// process. It does **not** produce usable sockets, or check that the descriptors
+/// are open: use [sockets] for that.
+pub fn sockets_diagnostic() -> Result<SocketDiagnostics, SocketError> {
+    let listen_fd_names = var(LISTEN_FDNAMES).ok();
+    let listen_fds_first_fd = var(LISTEN_FDS_FIRST_FD).ok();
+
+    diagnose(
+        &var(LISTEN_FDS)?,
+        listen_fd_names.as_deref(),
+        &var(LISTEN_PID)?,
+        listen_fds_first_fd.as_deref(),
+    )
+}
+
+fn diagnose(
+    listen_fds: &str,
+    listen_fd_names: Option<&str>,
+    listen_pid: &str,
+    listen_fds_first_fd: Option<&str>,
+) -> Result<SocketDiagnostics, SocketError> {
+    Ok(SocketDiagnostics {
+        fds: listen_fds
+            .parse()
+            .map_err(|_| SocketError::InvalidVar(LISTEN_FDS, listen_fds.into()))?,
+        names: listen_fd_names
+            .map(|names| names.split(':').map(str::to_owned).collect())
+            .unwrap_or_default(),
+        listen_pid: listen_pid
+            .parse()
+            .map_err(|_| SocketError::InvalidVar(LISTEN_PID, listen_pid.into()))?,
+        pid: process::id(),
+        first_fd: first_fd(listen_fds_first_fd)?,
+    })
+}
+
+/// What the `$LISTEN_*` variables say about passed sockets, from [sockets_diagnostic]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketDiagnostics {
+    fds: usize,
+    names: Vec<String>,
+    listen_pid: u32,
+    pid: u32,
+    first_fd: RawFd,
+}
+
+impl SocketDiagnostics {
+    /// The number of descriptors systemd passed, from `$LISTEN_FDS`
+    pub fn fds(&self) -> usize {
+        self.fds
+    }
+
+    /// The names of the descriptors, from `$LISTEN_FDNAMES`
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The PID the descriptors were passed to, from `$LISTEN_PID`
+    pub fn listen_pid(&self) -> u32 {
+        self.listen_pid
+    }
+
+    /// Our own PID
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The first passed descriptor, usually 3
+    pub fn first_fd(&self) -> RawFd {
+        self.first_fd
+    }
+
+    /// Whether the descriptors were passed to this process, so [sockets] would accept them
+    pub fn pid_matches(&self) -> bool {
+        self.listen_pid == self.pid
+    }
+
+    /// The number of descriptors, if they were passed to this process
+    fn checked_fds(&self) -> Result<usize, SocketError> {
+        if !self.pid_matches() {
+            return Err(SocketError::WrongPID(self.pid, self.listen_pid.to_string()));
+        }
+        Ok(self.fds)
+    }
+
+    /// Whether there is a name for every descriptor
+    pub fn names_match(&self) -> bool {
+        self.names.is_empty() || self.names.len() == self.fds
+    }
+}
+
 /// Represents a socket that systemd has passed to us
 #[derive(Debug)]
 pub struct SystemDSocket {
     name: Option<String>,
     fd: RawFd,
+    owned: bool,
 }
 
 impl SystemDSocket {
@@ -107,11 +335,40 @@ impl SystemDSocket {
         Self {
             name: Some(name.into()),
             fd,
+            owned: false,
         }
     }
 
     fn unnamed(fd: RawFd) -> Self {
-        Self { name: None, fd }
+        Self {
+            name: None,
+            fd,
+            owned: false,
+        }
+    }
+
+    /// Create a socket from a descriptor, as if systemd had passed it to us
+    ///
+    /// This is useful for testing code which consumes systemd sockets, e.g. with
+    /// one end of a `socketpair`, without running under systemd.
+    pub fn from_fd(name: Option<String>, fd: OwnedFd) -> Self {
+        Self {
+            name,
+            fd: fd.into_raw_fd(),
+            owned: false,
+        }
+    }
+
+    /// Split this socket into its name and descriptor, the inverse of [SystemDSocket::from_fd]
+    pub fn into_parts(mut self) -> (Option<String>, OwnedFd) {
+        let name = self.name.take();
+        (name, self.into())
+    }
+
+    /// Give up the descriptor, so that dropping the socket never closes it.
+    fn release(mut self) -> RawFd {
+        self.owned = false;
+        self.fd
     }
 
     /// Get the name of the socket, if it has one.
@@ -122,26 +379,773 @@ impl SystemDSocket {
         self.name.as_deref()
     }
 
-    /// Convert this socket into a `TcpListener`
+    /// Convert this socket into a non-blocking `TcpListener`
+    ///
+    /// The listener is ready to be handed to an async runtime. Use
+    /// [SystemDSocket::listener_blocking] for a classic blocking `accept`.
     pub fn listener(self) -> Result<TcpListener, SocketError> {
-        // Safety: This is how systemd rolls
-        // See: sd_listen_fds(3), the c API for accessing systemd sockets
-        let file = unsafe { File::from_raw_fd(self.fd) };
-        let metadata = file.metadata()?;
-        if !metadata.file_type().is_socket() {
-            return Err(SocketError::NotSocket(file.into_raw_fd()));
+        self.tcp_listener(true)
+    }
+
+    /// Convert this socket into a blocking `TcpListener`
+    ///
+    /// This is suitable for thread-per-connection servers, and matches the
+    /// blocking mode in which systemd passes descriptors.
+    pub fn listener_blocking(self) -> Result<TcpListener, SocketError> {
+        self.tcp_listener(false)
+    }
+
+    fn tcp_listener(self, nonblocking: bool) -> Result<TcpListener, SocketError> {
+        // Check the descriptor with fstat before taking ownership of it, so that
+        // no error path can close a descriptor which systemd handed to us.
+        if !is_socket(self.fd)? {
+            return Err(SocketError::NotSocket(self.fd));
+        }
+        if !is_listening(self.as_fd())? {
+            return Err(SocketError::NotListening(self.fd));
         }
 
         //Todo: We could manually check that this is an INET socket
         // here, so that we don't listen on some arbitrary socket?
 
-        // Safety: Above, we know that the FD is one we should be reading,
-        // and we just checked that the socket was one which is listening
-        // over tcp;
-        let listener = unsafe { TcpListener::from_raw_fd(file.into_raw_fd()) };
-        listener.set_nonblocking(true)?;
+        // Safety: This is how systemd rolls, see sd_listen_fds(3), the c API
+        // for accessing systemd sockets. Above, we checked that the FD is a socket.
+        let listener = unsafe { TcpListener::from_raw_fd(self.release()) };
+        listener.set_nonblocking(nonblocking)?;
         Ok(listener)
     }
+
+    /// Convert this socket into a [SeqPacketListener]
+    ///
+    /// This is for `ListenSequentialPacket=` sockets, which std has no type for.
+    /// The descriptor's blocking mode is left unchanged.
+    pub fn seqpacket_listener(self) -> Result<SeqPacketListener, SocketError> {
+        if !is_socket(self.fd)? {
+            return Err(SocketError::NotSocket(self.fd));
+        }
+
+        let kind: libc::c_int = getsockopt(self.as_fd(), libc::SOL_SOCKET, libc::SO_TYPE)?;
+        if kind != libc::SOCK_SEQPACKET {
+            return Err(SocketError::WrongType(self.fd, "SOCK_SEQPACKET"));
+        }
+        if !is_listening(self.as_fd())? {
+            return Err(SocketError::NotListening(self.fd));
+        }
+
+        Ok(SeqPacketListener {
+            fd: OwnedFd::from(self),
+        })
+    }
+
+    /// Convert this socket into a non-blocking `UnixDatagram`
+    ///
+    /// This is for `ListenDatagram=` sockets bound to a unix path, such as
+    /// metrics sockets. Any other kind of socket is rejected.
+    pub fn unix_datagram(self) -> Result<UnixDatagram, SocketError> {
+        if !is_socket(self.fd)? {
+            return Err(SocketError::NotSocket(self.fd));
+        }
+
+        if !is_unix_datagram(self.as_fd())? {
+            return Err(SocketError::WrongType(self.fd, "unix datagram"));
+        }
+
+        let socket = UnixDatagram::from(OwnedFd::from(self));
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    /// Convert this socket into a connected stream
+    ///
+    /// With `Accept=yes`, systemd accepts each connection itself and starts an
+    /// instance of the service with the connection as its only socket, in the
+    /// style of inetd. The descriptor's blocking mode is left unchanged.
+    /// Listening sockets are rejected with [SocketError::WrongType].
+    pub fn stream(self) -> Result<SocketStream, SocketError> {
+        if !is_socket(self.fd)? {
+            return Err(SocketError::NotSocket(self.fd));
+        }
+
+        let kind: libc::c_int = getsockopt(self.as_fd(), libc::SOL_SOCKET, libc::SO_TYPE)?;
+        if kind != libc::SOCK_STREAM || is_listening(self.as_fd())? {
+            return Err(SocketError::WrongType(self.fd, "connected stream"));
+        }
+
+        let domain: libc::c_int = getsockopt(self.as_fd(), libc::SOL_SOCKET, libc::SO_DOMAIN)?;
+        match domain {
+            libc::AF_INET | libc::AF_INET6 => {
+                Ok(SocketStream::Tcp(TcpStream::from(OwnedFd::from(self))))
+            }
+            libc::AF_UNIX => Ok(SocketStream::Unix(UnixStream::from(OwnedFd::from(self)))),
+            _ => Err(SocketError::WrongType(self.fd, "TCP or unix stream")),
+        }
+    }
+
+    /// Get the local address this socket is bound to, via `getsockname`
+    pub fn local_addr(&self) -> Result<SocketAddress, SocketError> {
+        let (storage, len) = self.sockname()?;
+        Ok(SocketAddress::from_raw(&storage, len))
+    }
+
+    /// Get the address family of this socket, via `getsockname`
+    ///
+    /// This distinguishes IPv4 and IPv6 sockets before converting them into listeners.
+    pub fn address_family(&self) -> Result<AddressFamily, SocketError> {
+        let (storage, _) = self.sockname()?;
+        Ok(match libc::c_int::from(storage.ss_family) {
+            libc::AF_INET => AddressFamily::Inet,
+            libc::AF_INET6 => AddressFamily::Inet6,
+            libc::AF_UNIX => AddressFamily::Unix,
+            family => AddressFamily::Other(family as libc::sa_family_t),
+        })
+    }
+
+    fn sockname(&self) -> Result<(libc::sockaddr_storage, libc::socklen_t), SocketError> {
+        // Safety: sockaddr_storage is plain-old-data, all zeroes is a valid value.
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+        // Safety: storage is large enough to hold any socket address.
+        let rc = unsafe {
+            libc::getsockname(
+                self.fd,
+                (&mut storage as *mut libc::sockaddr_storage).cast(),
+                &mut len,
+            )
+        };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOTSOCK) {
+                return Err(SocketError::NotSocket(self.fd));
+            }
+            return Err(err.into());
+        }
+
+        Ok((storage, len))
+    }
+
+    /// Duplicate this socket, with a new close-on-exec descriptor
+    ///
+    /// The clone keeps the same name, and can be converted independently of the
+    /// original. Unlike a socket passed by systemd, the clone owns its descriptor,
+    /// so it is closed if the clone is dropped without being converted.
+    pub fn try_clone(&self) -> Result<SystemDSocket, SocketError> {
+        // Safety: F_DUPFD_CLOEXEC only creates a new descriptor.
+        let fd = unsafe { libc::fcntl(self.fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(SystemDSocket {
+            name: self.name.clone(),
+            fd,
+            owned: true,
+        })
+    }
+
+    /// Read the peer credentials (`SO_PEERCRED`) of this socket
+    ///
+    /// See [peer_credentials] for use on accepted connections.
+    pub fn peer_cred(&self) -> Result<PeerCredentials, SocketError> {
+        peer_credentials(self)
+    }
+
+    /// The size of the socket's receive buffer (`SO_RCVBUF`), in bytes
+    pub fn recv_buffer_size(&self) -> Result<usize, SocketError> {
+        let size: libc::c_int = getsockopt(self.as_fd(), libc::SOL_SOCKET, libc::SO_RCVBUF)?;
+        Ok(size as usize)
+    }
+
+    /// The size of the socket's send buffer (`SO_SNDBUF`), in bytes
+    pub fn send_buffer_size(&self) -> Result<usize, SocketError> {
+        let size: libc::c_int = getsockopt(self.as_fd(), libc::SOL_SOCKET, libc::SO_SNDBUF)?;
+        Ok(size as usize)
+    }
+
+    /// Set the size of the socket's receive buffer (`SO_RCVBUF`)
+    ///
+    /// This is for enlarging buffers after activation, when the socket unit doesn't
+    /// set `ReceiveBuffer=`. Linux doubles the requested size to allow for
+    /// bookkeeping, and caps it at `net.core.rmem_max`, so
+    /// [SystemDSocket::recv_buffer_size] will not report `size` back.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), SocketError> {
+        setsockopt(
+            self.as_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            buffer_size(size),
+        )?;
+        Ok(())
+    }
+
+    /// Set the size of the socket's send buffer (`SO_SNDBUF`)
+    ///
+    /// As with [SystemDSocket::set_recv_buffer_size], the kernel doubles the
+    /// requested size, capped at `net.core.wmem_max`.
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), SocketError> {
+        setsockopt(
+            self.as_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            buffer_size(size),
+        )?;
+        Ok(())
+    }
+}
+
+/// A buffer size as a socket option value, saturating at `c_int::MAX`.
+fn buffer_size(size: usize) -> libc::c_int {
+    size.try_into().unwrap_or(libc::c_int::MAX)
+}
+
+/// Pass systemd sockets on to a child process
+///
+/// This follows the `sd_listen_fds(3)` protocol, so that the child can claim the
+/// sockets with [sockets] just as if systemd had started it directly.
+pub trait SystemDCommandExt {
+    /// Pass `sockets` to the child as descriptors `3..3+n`, and set
+    /// `$LISTEN_FDS`, `$LISTEN_FDNAMES` and `$LISTEN_PID` for it.
+    ///
+    /// `$LISTEN_PID` is only known once the child has forked, so the child's
+    /// environment is built here, from this process's environment, with a slot
+    /// for the PID which is filled in after the fork without allocating. Any
+    /// `$LISTEN_FDS_FIRST_FD` is removed, since the child's descriptors start at 3.
+    /// Modifying the command's environment (e.g. with [process::Command::env])
+    /// makes std replace that environment when it execs, and so must not be
+    /// combined with this. Likewise, changes to this process's environment made
+    /// after calling this are not passed on.
+    fn with_systemd_sockets(&mut self, sockets: &[SystemDSocket]) -> &mut Self;
+}
+
+impl SystemDCommandExt for process::Command {
+    fn with_systemd_sockets(&mut self, sockets: &[SystemDSocket]) -> &mut Self {
+        let fds: Vec<RawFd> = sockets.iter().map(|socket| socket.fd).collect();
+        let names: Vec<&str> = sockets
+            .iter()
+            .map(|socket| socket.name().unwrap_or(UNKNOWN_NAME))
+            .collect();
+
+        // Allocate everything up front, since the child may only use async-signal-safe calls.
+        let mut env = ChildEnv::new(std::env::vars_os(), fds.len(), &names.join(":"));
+        let mut moved = Vec::with_capacity(fds.len());
+
+        let remap = move || {
+            let first = SD_FD_OFFSET + fds.len() as RawFd;
+
+            // Move every descriptor above the target range first, so that
+            // placing one can't clobber another which is still to be moved.
+            moved.clear();
+            for fd in &fds {
+                let tmp = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, first) };
+                if tmp < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                moved.push(tmp);
+            }
+
+            // dup2 clears close-on-exec, so only the targets survive the exec.
+            for (target, fd) in (SD_FD_OFFSET..).zip(&moved) {
+                if unsafe { libc::dup2(*fd, target) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            env.install(unsafe { libc::getpid() } as u32);
+            Ok(())
+        };
+
+        // Safety: `moved` has capacity for every descriptor, so the closure doesn't
+        // allocate. It only calls fcntl, dup2 and getpid, which are async-signal-safe,
+        // and writes into memory which was allocated before the fork.
+        unsafe { self.pre_exec(remap) }
+    }
+}
+
+extern "C" {
+    static mut environ: *const *const libc::c_char;
+}
+
+/// The environment for a child which is passed sockets, built before forking.
+struct ChildEnv {
+    /// `NAME=value` entries, each NUL-terminated, starting with the `$LISTEN_PID` slot
+    vars: Vec<Vec<u8>>,
+
+    /// Pointers into `vars`, terminated by a null pointer
+    envp: Vec<*const libc::c_char>,
+}
+
+// Safety: the pointers in `envp` point into the heap buffers owned by `vars`, which
+// are only ever written through `&mut self`.
+unsafe impl Send for ChildEnv {}
+unsafe impl Sync for ChildEnv {}
+
+impl ChildEnv {
+    /// `LISTEN_PID=`, followed by room for a NUL-terminated PID, see [format_pid].
+    const PID_PREFIX: usize = LISTEN_PID.len() + 1;
+
+    fn new<I>(vars: I, fds: usize, names: &str) -> Self
+    where
+        I: IntoIterator<Item = (std::ffi::OsString, std::ffi::OsString)>,
+    {
+        let entry = |name: &OsStr, value: &OsStr| {
+            let mut entry = name.as_bytes().to_vec();
+            entry.push(b'=');
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(0);
+            entry
+        };
+
+        let mut pid = format!("{LISTEN_PID}=").into_bytes();
+        pid.resize(Self::PID_PREFIX + 12, 0);
+
+        let mut env = vec![
+            pid,
+            entry(OsStr::new(LISTEN_FDS), OsStr::new(&fds.to_string())),
+            entry(OsStr::new(LISTEN_FDNAMES), OsStr::new(names)),
+        ];
+        env.extend(
+            vars.into_iter()
+                .filter(|(name, _)| {
+                    ![LISTEN_PID, LISTEN_FDS, LISTEN_FDNAMES, LISTEN_FDS_FIRST_FD]
+                        .iter()
+                        .any(|listen| name == listen)
+                })
+                .filter(|(name, value)| {
+                    !name.as_bytes().contains(&0) && !value.as_bytes().contains(&0)
+                })
+                .map(|(name, value)| entry(&name, &value)),
+        );
+
+        let envp = env
+            .iter()
+            .map(|entry| entry.as_ptr().cast())
+            .chain([std::ptr::null()])
+            .collect();
+
+        Self { vars: env, envp }
+    }
+
+    /// Fill in `$LISTEN_PID`, and make this the process environment for exec.
+    ///
+    /// This neither allocates nor takes libc's environment lock.
+    fn install(&mut self, pid: u32) {
+        self.set_pid(pid);
+
+        // Safety: envp is null-terminated, and points at NUL-terminated entries
+        // which live as long as the closure, which is until exec.
+        unsafe { environ = self.envp.as_ptr() };
+    }
+
+    fn set_pid(&mut self, pid: u32) {
+        let slot: &mut [u8; 12] = (&mut self.vars[0][Self::PID_PREFIX..])
+            .try_into()
+            .expect("slot is sized for a PID");
+        format_pid(slot, pid);
+    }
+}
+
+/// Write `pid` as a NUL-terminated decimal string, without allocating.
+fn format_pid(buf: &mut [u8; 12], mut pid: u32) {
+    let mut digits = [0u8; 10];
+    let mut n = 0;
+    loop {
+        digits[n] = b'0' + (pid % 10) as u8;
+        pid /= 10;
+        n += 1;
+        if pid == 0 {
+            break;
+        }
+    }
+
+    for (i, digit) in digits[..n].iter().rev().enumerate() {
+        buf[i] = *digit;
+    }
+    buf[n] = 0;
+}
+
+/// A collection of systemd sockets, keyed by their names
+///
+/// Sockets without a name, or whose name duplicates that of an earlier socket,
+/// are kept separately and are available from [SystemDSockets::unnamed].
+#[derive(Debug, Default)]
+pub struct SystemDSockets {
+    named: HashMap<String, SystemDSocket>,
+    unnamed: Vec<SystemDSocket>,
+}
+
+impl SystemDSockets {
+    /// Get the socket with the given name
+    pub fn get(&self, name: &str) -> Option<&SystemDSocket> {
+        self.named.get(name)
+    }
+
+    /// Remove and return the socket with the given name
+    pub fn remove(&mut self, name: &str) -> Option<SystemDSocket> {
+        self.named.remove(name)
+    }
+
+    /// Sockets which could not be looked up by name
+    pub fn unnamed(&self) -> &[SystemDSocket] {
+        &self.unnamed
+    }
+
+    /// Remove the socket with the given name, as a non-blocking TCP listener
+    ///
+    /// If the socket is not a TCP socket, it is left in place and
+    /// [SocketError::WrongType] is returned, or [SocketError::NotListening] if
+    /// it is a TCP connection rather than a listener.
+    pub fn take_listener(&mut self, name: &str) -> Result<TcpListener, SocketError> {
+        let socket = self.lookup(name)?;
+        if !is_tcp(socket.as_fd())? {
+            return Err(SocketError::WrongType(socket.fd, "TCP"));
+        }
+        if !is_listening(socket.as_fd())? {
+            return Err(SocketError::NotListening(socket.fd));
+        }
+        self.named.remove(name).expect("socket exists").listener()
+    }
+
+    /// Remove the socket with the given name, as a non-blocking unix datagram socket
+    ///
+    /// If the socket is not a unix datagram socket, it is left in place and
+    /// [SocketError::WrongType] is returned.
+    pub fn take_datagram(&mut self, name: &str) -> Result<UnixDatagram, SocketError> {
+        let socket = self.lookup(name)?;
+        if !is_socket(socket.fd)? {
+            return Err(SocketError::NotSocket(socket.fd));
+        }
+        if !is_unix_datagram(socket.as_fd())? {
+            return Err(SocketError::WrongType(socket.fd, "unix datagram"));
+        }
+        self.named
+            .remove(name)
+            .expect("socket exists")
+            .unix_datagram()
+    }
+
+    fn lookup(&self, name: &str) -> Result<&SystemDSocket, SocketError> {
+        self.named
+            .get(name)
+            .ok_or_else(|| SocketError::MissingName(name.into()))
+    }
+}
+
+impl FromIterator<SystemDSocket> for SystemDSockets {
+    fn from_iter<I: IntoIterator<Item = SystemDSocket>>(iter: I) -> Self {
+        let mut sockets = SystemDSockets::default();
+
+        for socket in iter {
+            match socket.name.clone() {
+                Some(name) if !sockets.named.contains_key(&name) => {
+                    sockets.named.insert(name, socket);
+                }
+                Some(name) => {
+                    tracing::warn!("Duplicate systemd socket name {name}");
+                    sockets.unnamed.push(socket);
+                }
+                None => sockets.unnamed.push(socket),
+            }
+        }
+
+        sockets
+    }
+}
+
+impl IntoIterator for SystemDSockets {
+    type Item = SystemDSocket;
+    type IntoIter = std::vec::IntoIter<SystemDSocket>;
+
+    /// Iterate over all sockets, named or not, in file descriptor order
+    fn into_iter(self) -> Self::IntoIter {
+        let mut sockets: Vec<_> = self.named.into_values().chain(self.unnamed).collect();
+        sockets.sort_by_key(|socket| socket.fd);
+        sockets.into_iter()
+    }
+}
+
+/// A listening `SOCK_SEQPACKET` socket
+#[derive(Debug)]
+pub struct SeqPacketListener {
+    fd: OwnedFd,
+}
+
+impl SeqPacketListener {
+    /// Accept a new connection, returning its descriptor
+    ///
+    /// The accepted descriptor is close-on-exec.
+    pub fn accept(&self) -> io::Result<OwnedFd> {
+        // Safety: We don't need the peer address, so passing null is allowed.
+        let fd = unsafe {
+            libc::accept4(
+                self.fd.as_raw_fd(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Safety: accept4 succeeded, so this is a new descriptor we own.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+impl AsRawFd for SeqPacketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for SeqPacketListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl From<SeqPacketListener> for OwnedFd {
+    fn from(listener: SeqPacketListener) -> Self {
+        listener.fd
+    }
+}
+
+/// A connection passed by an `Accept=yes` socket unit, see [SystemDSocket::stream]
+#[derive(Debug)]
+pub enum SocketStream {
+    /// A TCP connection
+    Tcp(TcpStream),
+
+    /// A unix stream connection
+    Unix(UnixStream),
+}
+
+impl Read for SocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SocketStream::Tcp(stream) => stream.read(buf),
+            SocketStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for SocketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SocketStream::Tcp(stream) => stream.write(buf),
+            SocketStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.flush(),
+            SocketStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for SocketStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+impl AsFd for SocketStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            SocketStream::Tcp(stream) => stream.as_fd(),
+            SocketStream::Unix(stream) => stream.as_fd(),
+        }
+    }
+}
+
+/// The address family of a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// IPv4 (`AF_INET`)
+    Inet,
+
+    /// IPv6 (`AF_INET6`)
+    Inet6,
+
+    /// Unix domain sockets (`AF_UNIX`)
+    Unix,
+
+    /// Another address family
+    Other(libc::sa_family_t),
+}
+
+/// The local address a socket is bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketAddress {
+    /// An IPv4 or IPv6 address
+    Inet(SocketAddr),
+
+    /// A unix socket bound to a filesystem path
+    Unix(PathBuf),
+
+    /// A unix socket bound to a name in the abstract namespace
+    Abstract(Vec<u8>),
+
+    /// A unix socket which is not bound to any address
+    Unnamed,
+
+    /// A socket from another address family
+    Other(libc::sa_family_t),
+}
+
+impl SocketAddress {
+    fn from_raw(storage: &libc::sockaddr_storage, len: libc::socklen_t) -> Self {
+        match libc::c_int::from(storage.ss_family) {
+            libc::AF_INET => {
+                // Safety: the address family tells us this is a sockaddr_in.
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                SocketAddress::Inet(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes()),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                // Safety: the address family tells us this is a sockaddr_in6.
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                SocketAddress::Inet(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            libc::AF_UNIX => {
+                // Safety: the address family tells us this is a sockaddr_un.
+                let addr = unsafe { &*(storage as *const _ as *const libc::sockaddr_un) };
+                let path_len = (len as usize)
+                    .saturating_sub(mem::size_of::<libc::sa_family_t>())
+                    .min(addr.sun_path.len());
+                let path: Vec<u8> = addr.sun_path[..path_len].iter().map(|c| *c as u8).collect();
+
+                match path.split_first() {
+                    None => SocketAddress::Unnamed,
+                    Some((0, name)) => SocketAddress::Abstract(name.to_vec()),
+                    Some(_) => {
+                        let end = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+                        SocketAddress::Unix(OsStr::from_bytes(&path[..end]).into())
+                    }
+                }
+            }
+            family => SocketAddress::Other(family as libc::sa_family_t),
+        }
+    }
+}
+
+/// Credentials of the process on the other end of a unix socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// The process ID of the peer
+    pub pid: u32,
+
+    /// The user ID of the peer
+    pub uid: u32,
+
+    /// The group ID of the peer
+    pub gid: u32,
+}
+
+/// Read the peer credentials (`SO_PEERCRED`) of a unix socket.
+///
+/// This is most useful on connections accepted from a systemd-provided
+/// unix listener, to check the privileges of the connecting client.
+pub fn peer_credentials<F: AsFd>(socket: &F) -> Result<PeerCredentials, SocketError> {
+    let cred: libc::ucred = getsockopt(socket.as_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED)?;
+
+    Ok(PeerCredentials {
+        pid: cred.pid as u32,
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+/// Check whether the descriptor is an IPv4 or IPv6 stream socket.
+fn is_tcp(fd: BorrowedFd<'_>) -> Result<bool, SocketError> {
+    if !is_socket(fd.as_raw_fd())? {
+        return Err(SocketError::NotSocket(fd.as_raw_fd()));
+    }
+
+    let kind: libc::c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE)?;
+    let domain: libc::c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_DOMAIN)?;
+    Ok(kind == libc::SOCK_STREAM && matches!(domain, libc::AF_INET | libc::AF_INET6))
+}
+
+fn is_unix_datagram(fd: BorrowedFd<'_>) -> io::Result<bool> {
+    let kind: libc::c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE)?;
+    let domain: libc::c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_DOMAIN)?;
+    Ok(kind == libc::SOCK_DGRAM && domain == libc::AF_UNIX)
+}
+
+/// Check whether the socket is listening for connections, rather than e.g. connected.
+fn is_listening(fd: BorrowedFd<'_>) -> io::Result<bool> {
+    let listening: libc::c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_ACCEPTCONN)?;
+    Ok(listening != 0)
+}
+
+/// Check whether the descriptor is a socket, without taking ownership of it.
+fn is_socket(fd: RawFd) -> io::Result<bool> {
+    // Safety: stat is plain-old-data, all zeroes is a valid value.
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+    // Safety: fstat only writes into the provided stat buffer.
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stat.st_mode & libc::S_IFMT == libc::S_IFSOCK)
+}
+
+/// Read a socket option of type `T` from the socket.
+fn getsockopt<T>(fd: BorrowedFd<'_>, level: libc::c_int, name: libc::c_int) -> io::Result<T> {
+    // Safety: socket options are plain-old-data, all zeroes is a valid value.
+    let mut value: T = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+
+    // Safety: value has room for `len` bytes, which getsockopt will not exceed.
+    let rc = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            (&mut value as *mut T).cast(),
+            &mut len,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(value)
+}
+
+/// Set a socket option of type `T` on the socket.
+fn setsockopt<T>(
+    fd: BorrowedFd<'_>,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: T,
+) -> io::Result<()> {
+    // Safety: value is valid for reads of `size_of::<T>()` bytes.
+    let rc = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            (&value as *const T).cast(),
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
 }
 
 impl AsRawFd for SystemDSocket {
@@ -156,9 +1160,27 @@ impl AsFd for SystemDSocket {
     }
 }
 
+impl From<SystemDSocket> for OwnedFd {
+    fn from(socket: SystemDSocket) -> Self {
+        // Safety: The socket owns its descriptor, which systemd handed to us,
+        // and consuming the socket transfers that ownership.
+        unsafe { OwnedFd::from_raw_fd(socket.release()) }
+    }
+}
+
+impl Drop for SystemDSocket {
+    fn drop(&mut self) {
+        if self.owned {
+            // Safety: Only a clone owns its descriptor, which nothing else refers to.
+            drop(unsafe { OwnedFd::from_raw_fd(self.fd) });
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
 
     #[test]
     fn parse_variables() {
@@ -169,6 +1191,7 @@ mod tests {
             listen_fds,
             Some(listen_fd_names),
             &format!("{}", process::id()),
+            None,
         )
         .unwrap();
 
@@ -178,4 +1201,583 @@ mod tests {
         let fds: Vec<_> = sockets.iter().map(|s| s.fd).collect();
         assert_eq!(fds, vec![3, 4, 5]);
     }
+
+    #[test]
+    fn local_addr_inet() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = SystemDSocket::unnamed(listener.as_raw_fd());
+
+        assert_eq!(
+            socket.local_addr().unwrap(),
+            SocketAddress::Inet(listener.local_addr().unwrap())
+        );
+    }
+
+    #[test]
+    fn address_family() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let socket = SystemDSocket::unnamed(left.as_raw_fd());
+        assert_eq!(socket.address_family().unwrap(), AddressFamily::Unix);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = SystemDSocket::unnamed(listener.as_raw_fd());
+        assert_eq!(socket.address_family().unwrap(), AddressFamily::Inet);
+
+        if let Ok(listener) = TcpListener::bind("[::1]:0") {
+            let socket = SystemDSocket::unnamed(listener.as_raw_fd());
+            assert_eq!(socket.address_family().unwrap(), AddressFamily::Inet6);
+        }
+    }
+
+    #[test]
+    fn local_addr_unix() {
+        let path = std::env::temp_dir().join(format!("systemd-local-addr-{}", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let socket = SystemDSocket::unnamed(listener.as_raw_fd());
+
+        assert_eq!(
+            socket.local_addr().unwrap(),
+            SocketAddress::Unix(path.clone())
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let socket = SystemDSocket::unnamed(left.as_raw_fd());
+        assert_eq!(socket.local_addr().unwrap(), SocketAddress::Unnamed);
+    }
+
+    #[test]
+    fn local_addr_not_socket() {
+        let file = File::open("/dev/null").unwrap();
+        let socket = SystemDSocket::unnamed(file.as_raw_fd());
+
+        assert!(matches!(
+            socket.local_addr(),
+            Err(SocketError::NotSocket(fd)) if fd == file.as_raw_fd()
+        ));
+    }
+
+    #[test]
+    fn listener_not_socket_keeps_fd_open() {
+        let file = File::open("/dev/null").unwrap();
+        let fd = file.as_raw_fd();
+
+        assert!(matches!(
+            SystemDSocket::unnamed(fd).listener(),
+            Err(SocketError::NotSocket(n)) if n == fd
+        ));
+
+        // The descriptor must still be open, and owned by `file`.
+        assert!(file.metadata().is_ok());
+    }
+
+    #[test]
+    fn listener_not_listening() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let fd = stream.as_raw_fd();
+
+        assert!(matches!(
+            SystemDSocket::unnamed(fd).listener(),
+            Err(SocketError::NotListening(n)) if n == fd
+        ));
+        assert!(stream.peer_addr().is_ok());
+
+        let mut sockets: SystemDSockets = [SystemDSocket::new("web", stream.into_raw_fd())]
+            .into_iter()
+            .collect();
+        assert!(matches!(
+            sockets.take_listener("web"),
+            Err(SocketError::NotListening(_))
+        ));
+        assert!(sockets.get("web").is_some());
+    }
+
+    #[test]
+    fn accepted_stream() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let (accepted, _) = server.accept().unwrap();
+
+        let mut stream = SystemDSocket::unnamed(accepted.into_raw_fd())
+            .stream()
+            .unwrap();
+        assert!(matches!(stream, SocketStream::Tcp(_)));
+        stream.write_all(b"hi").unwrap();
+        let mut buf = [0; 2];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+
+        let (left, _right) = UnixStream::pair().unwrap();
+        let stream = SystemDSocket::unnamed(left.into_raw_fd()).stream().unwrap();
+        assert!(matches!(stream, SocketStream::Unix(_)));
+
+        let fd = server.as_raw_fd();
+        assert!(matches!(
+            SystemDSocket::unnamed(fd).stream(),
+            Err(SocketError::WrongType(n, "connected stream")) if n == fd
+        ));
+        let (datagram, _peer) = UnixDatagram::pair().unwrap();
+        let fd = datagram.as_raw_fd();
+        assert!(matches!(
+            SystemDSocket::unnamed(fd).stream(),
+            Err(SocketError::WrongType(n, "connected stream")) if n == fd
+        ));
+    }
+
+    #[test]
+    fn buffer_sizes() {
+        let (datagram, _peer) = UnixDatagram::pair().unwrap();
+        let socket = SystemDSocket::unnamed(datagram.as_raw_fd());
+
+        socket.set_recv_buffer_size(4096).unwrap();
+        socket.set_send_buffer_size(8192).unwrap();
+        assert!(socket.recv_buffer_size().unwrap() >= 4096);
+        assert!(socket.send_buffer_size().unwrap() >= 8192);
+        assert!(socket.send_buffer_size().unwrap() > socket.recv_buffer_size().unwrap());
+
+        let file = File::open("/dev/null").unwrap();
+        let socket = SystemDSocket::unnamed(file.as_raw_fd());
+        assert!(matches!(socket.recv_buffer_size(), Err(SocketError::IO(_))));
+        assert!(matches!(
+            socket.set_send_buffer_size(4096),
+            Err(SocketError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn parse_first_fd() {
+        let pid = format!("{}", process::id());
+        let sockets = construct_sockets("2", None, &pid, Some("10")).unwrap();
+
+        let fds: Vec<_> = sockets.iter().map(|s| s.fd).collect();
+        assert_eq!(fds, vec![10, 11]);
+
+        assert!(matches!(
+            construct_sockets("2", None, &pid, Some("-1")),
+            Err(SocketError::InvalidVar(LISTEN_FDS_FIRST_FD, _))
+        ));
+    }
+
+    #[test]
+    fn parse_too_many_names() {
+        let sockets = construct_sockets(
+            "2",
+            Some("alice:bob:charlie"),
+            &format!("{}", process::id()),
+            None,
+        )
+        .unwrap();
+
+        let names: Vec<_> = sockets.iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec![Some("alice"), Some("bob")]);
+    }
+
+    #[test]
+    fn parse_too_few_names() {
+        let sockets =
+            construct_sockets("3", Some("alice"), &format!("{}", process::id()), None).unwrap();
+
+        let names: Vec<_> = sockets.iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec![Some("alice"), None, None]);
+
+        let fds: Vec<_> = sockets.iter().map(|s| s.fd).collect();
+        assert_eq!(fds, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn parse_unknown_name() {
+        let sockets = construct_sockets(
+            "2",
+            Some("unknown:web"),
+            &format!("{}", process::id()),
+            None,
+        )
+        .unwrap();
+
+        let names: Vec<_> = sockets.iter().map(|s| s.name()).collect();
+        assert_eq!(names, vec![None, Some("web")]);
+    }
+
+    #[test]
+    fn sockets_by_name() {
+        let mut sockets: SystemDSockets = construct_sockets(
+            "3",
+            Some("web:admin:web"),
+            &format!("{}", process::id()),
+            None,
+        )
+        .unwrap()
+        .into_iter()
+        .collect();
+
+        assert_eq!(sockets.get("web").unwrap().fd, 3);
+        assert_eq!(sockets.get("admin").unwrap().fd, 4);
+        assert!(sockets.get("metrics").is_none());
+
+        let unnamed: Vec<_> = sockets.unnamed().iter().map(|s| s.fd).collect();
+        assert_eq!(unnamed, vec![5]);
+
+        let admin = sockets.remove("admin").unwrap();
+        assert_eq!(admin.fd, 4);
+        assert!(sockets.get("admin").is_none());
+
+        let fds: Vec<_> = sockets.into_iter().map(|s| s.fd).collect();
+        assert_eq!(fds, vec![3, 5]);
+    }
+
+    fn seqpacket_socket() -> OwnedFd {
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) };
+        assert!(fd >= 0, "socket: {}", io::Error::last_os_error());
+        unsafe { OwnedFd::from_raw_fd(fd) }
+    }
+
+    fn unix_address(path: &std::path::Path) -> libc::sockaddr_un {
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, src) in addr.sun_path.iter_mut().zip(path.as_os_str().as_bytes()) {
+            *dst = *src as libc::c_char;
+        }
+        addr
+    }
+
+    #[test]
+    fn seqpacket_listener_accepts() {
+        let path = std::env::temp_dir().join(format!("systemd-seqpacket-{}", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let addr = unix_address(&path);
+        let addr_len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+        let server = seqpacket_socket();
+        unsafe {
+            assert_eq!(
+                libc::bind(
+                    server.as_raw_fd(),
+                    (&addr as *const libc::sockaddr_un).cast(),
+                    addr_len
+                ),
+                0
+            );
+            assert_eq!(libc::listen(server.as_raw_fd(), 1), 0);
+        }
+
+        let listener = SystemDSocket::unnamed(server.into_raw_fd())
+            .seqpacket_listener()
+            .unwrap();
+
+        let client = seqpacket_socket();
+        unsafe {
+            assert_eq!(
+                libc::connect(
+                    client.as_raw_fd(),
+                    (&addr as *const libc::sockaddr_un).cast(),
+                    addr_len
+                ),
+                0
+            );
+        }
+
+        let conn = listener.accept().unwrap();
+        let kind: libc::c_int = getsockopt(conn.as_fd(), libc::SOL_SOCKET, libc::SO_TYPE).unwrap();
+        assert_eq!(kind, libc::SOCK_SEQPACKET);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seqpacket_listener_wrong_type() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let fd = left.as_raw_fd();
+
+        assert!(matches!(
+            SystemDSocket::unnamed(fd).seqpacket_listener(),
+            Err(SocketError::WrongType(n, "SOCK_SEQPACKET")) if n == fd
+        ));
+    }
+
+    #[test]
+    fn unix_datagram() {
+        let (left, right) = UnixDatagram::pair().unwrap();
+        let socket = SystemDSocket::unnamed(left.into_raw_fd())
+            .unix_datagram()
+            .unwrap();
+        assert!(is_nonblocking(&socket));
+
+        right.send(b"metric:1|c").unwrap();
+        let mut buf = [0; 16];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"metric:1|c");
+
+        let (stream, _other) = std::os::unix::net::UnixStream::pair().unwrap();
+        let fd = stream.as_raw_fd();
+        assert!(matches!(
+            SystemDSocket::unnamed(fd).unix_datagram(),
+            Err(SocketError::WrongType(n, "unix datagram")) if n == fd
+        ));
+    }
+
+    #[test]
+    fn take_by_name() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        let (datagram, peer) = UnixDatagram::pair().unwrap();
+        let mut sockets: SystemDSockets = [
+            SystemDSocket::new("web", tcp.into_raw_fd()),
+            SystemDSocket::new("metrics", datagram.into_raw_fd()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert!(matches!(
+            sockets.take_listener("metrics"),
+            Err(SocketError::WrongType(_, "TCP"))
+        ));
+        assert!(matches!(
+            sockets.take_datagram("web"),
+            Err(SocketError::WrongType(_, "unix datagram"))
+        ));
+        assert!(matches!(
+            sockets.take_listener("admin"),
+            Err(SocketError::MissingName(name)) if name == "admin"
+        ));
+
+        let listener = sockets.take_listener("web").unwrap();
+        assert_eq!(listener.local_addr().unwrap(), address);
+
+        let metrics = sockets.take_datagram("metrics").unwrap();
+        peer.send(b"up").unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(metrics.recv(&mut buf).unwrap(), 2);
+        assert!(sockets.get("web").is_none());
+    }
+
+    fn is_nonblocking<F: AsRawFd>(fd: &F) -> bool {
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        assert!(flags >= 0);
+        flags & libc::O_NONBLOCK != 0
+    }
+
+    #[test]
+    fn listener_blocking_mode() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener = SystemDSocket::unnamed(listener.into_raw_fd())
+            .listener()
+            .unwrap();
+        assert!(is_nonblocking(&listener));
+
+        let listener = SystemDSocket::unnamed(listener.into_raw_fd())
+            .listener_blocking()
+            .unwrap();
+        assert!(!is_nonblocking(&listener));
+    }
+
+    #[test]
+    fn into_owned_fd() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let fd = left.into_raw_fd();
+
+        let owned = OwnedFd::from(SystemDSocket::unnamed(fd));
+        assert_eq!(owned.as_raw_fd(), fd);
+
+        let stream = std::os::unix::net::UnixStream::from(owned);
+        assert_eq!(stream.as_raw_fd(), fd);
+    }
+
+    #[test]
+    fn diagnose_variables() {
+        let diagnostics = diagnose("2", Some("web:admin:extra"), "1", Some("5")).unwrap();
+        assert_eq!(diagnostics.fds(), 2);
+        assert_eq!(diagnostics.names(), ["web", "admin", "extra"]);
+        assert_eq!(diagnostics.listen_pid(), 1);
+        assert_eq!(diagnostics.pid(), process::id());
+        assert_eq!(diagnostics.first_fd(), 5);
+        assert!(!diagnostics.pid_matches());
+        assert!(!diagnostics.names_match());
+
+        assert!(matches!(
+            diagnostics.checked_fds(),
+            Err(SocketError::WrongPID(_, pid)) if pid == "1"
+        ));
+
+        let diagnostics = diagnose("1", None, &process::id().to_string(), None).unwrap();
+        assert!(diagnostics.pid_matches());
+        assert_eq!(diagnostics.checked_fds().unwrap(), 1);
+        assert!(diagnostics.names_match());
+        assert_eq!(diagnostics.first_fd(), SD_FD_OFFSET);
+
+        assert!(matches!(
+            diagnose("x", None, "1", None),
+            Err(SocketError::InvalidVar(LISTEN_FDS, _))
+        ));
+    }
+
+    #[test]
+    fn from_fd() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let socket = SystemDSocket::from_fd(Some("pair".into()), OwnedFd::from(left));
+
+        assert_eq!(socket.name(), Some("pair"));
+        assert_eq!(socket.peer_cred().unwrap().pid, process::id());
+
+        let (name, fd) = socket.into_parts();
+        assert_eq!(name.as_deref(), Some("pair"));
+        assert!(is_socket(fd.as_raw_fd()).unwrap());
+    }
+
+    #[test]
+    fn pass_sockets_to_child() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (other, _other_right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let sockets = [
+            SystemDSocket::new("web", left.as_raw_fd()),
+            SystemDSocket::unnamed(other.as_raw_fd()),
+        ];
+
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(r#"echo "$LISTEN_FDS $LISTEN_FDNAMES"; [ "$LISTEN_PID" = "$$" ] && [ -S /dev/fd/3 ] && [ -S /dev/fd/4 ] && echo ok"#)
+            .with_systemd_sockets(&sockets)
+            .output()
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "2 web:unknown\nok\n"
+        );
+    }
+
+    #[test]
+    fn child_environment() {
+        let parent = [
+            ("PATH", "/usr/bin"),
+            ("LISTEN_FDS", "5"),
+            ("LISTEN_FDS_FIRST_FD", "10"),
+            ("LISTEN_PID", "1"),
+        ]
+        .map(|(name, value)| (name.into(), value.into()));
+        let mut env = ChildEnv::new(parent, 2, "web:unknown");
+        env.set_pid(4242);
+
+        let vars: Vec<_> = env
+            .vars
+            .iter()
+            .map(|entry| {
+                std::ffi::CStr::from_bytes_until_nul(entry)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            vars,
+            [
+                "LISTEN_PID=4242",
+                "LISTEN_FDS=2",
+                "LISTEN_FDNAMES=web:unknown",
+                "PATH=/usr/bin"
+            ]
+        );
+        assert_eq!(env.envp.len(), 5);
+        assert!(env.envp[4].is_null());
+    }
+
+    #[test]
+    fn tcp_listeners_in_order() {
+        let first = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+        let (pair, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+
+        let sockets = vec![
+            SystemDSocket::new("first", first.into_raw_fd()),
+            SystemDSocket::unnamed(pair.as_raw_fd()),
+            SystemDSocket::unnamed(second.into_raw_fd()),
+        ];
+        let listeners = tcp_listeners(sockets, false).unwrap();
+
+        let found: Vec<_> = listeners
+            .iter()
+            .map(|(name, listener)| (name.as_deref(), listener.local_addr().unwrap()))
+            .collect();
+        assert_eq!(found, vec![(Some("first"), addrs[0]), (None, addrs[1])]);
+
+        let sockets = vec![SystemDSocket::unnamed(pair.as_raw_fd())];
+        assert!(matches!(
+            tcp_listeners(sockets, true),
+            Err(SocketError::WrongType(fd, "TCP")) if fd == pair.as_raw_fd()
+        ));
+    }
+
+    fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+
+    #[test]
+    fn tcp_listeners_skip_fifos() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (reader, writer) = pipe().unwrap();
+
+        let sockets = vec![
+            SystemDSocket::new("fifo", reader.as_raw_fd()),
+            SystemDSocket::new("web", listener.into_raw_fd()),
+        ];
+        let listeners = tcp_listeners(sockets, false).unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].1.local_addr().unwrap(), addr);
+
+        // The skipped pipe is still open.
+        assert!(File::from(writer).write_all(b"x").is_ok());
+
+        let sockets = vec![SystemDSocket::unnamed(reader.as_raw_fd())];
+        assert!(matches!(
+            tcp_listeners(sockets, true),
+            Err(SocketError::NotSocket(fd)) if fd == reader.as_raw_fd()
+        ));
+    }
+
+    #[test]
+    fn try_clone() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+        let socket = SystemDSocket::from_fd(Some("pair".into()), OwnedFd::from(left));
+
+        let clone = socket.try_clone().unwrap();
+        assert_eq!(clone.name(), Some("pair"));
+        assert_ne!(clone.fd, socket.fd);
+
+        drop(OwnedFd::from(socket));
+        assert!(clone.peer_cred().is_ok());
+        drop(OwnedFd::from(clone));
+
+        // A clone which is dropped without being converted closes its descriptor,
+        // so the peer sees the end of the stream once the original is closed too.
+        let (left, mut right) = std::os::unix::net::UnixStream::pair().unwrap();
+        right
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let socket = SystemDSocket::from_fd(None, OwnedFd::from(left));
+        drop(socket.try_clone().unwrap());
+        drop(OwnedFd::from(socket));
+        assert_eq!(right.read(&mut [0; 1]).unwrap(), 0);
+
+        assert!(matches!(
+            SystemDSocket::unnamed(-1).try_clone(),
+            Err(SocketError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn peer_credentials_of_socketpair() {
+        let (left, _right) = std::os::unix::net::UnixStream::pair().unwrap();
+
+        let cred = peer_credentials(&left).unwrap();
+        assert_eq!(cred.pid, process::id());
+        assert_eq!(cred.uid, unsafe { libc::getuid() });
+        assert_eq!(cred.gid, unsafe { libc::getgid() });
+    }
 }